@@ -2,14 +2,9 @@ use std::convert::TryInto;
 use std::path::Path;
 use std::fs;
 use std::time::Instant;
-use dicom::object::open_file;
 use anyhow::{Result, anyhow};
 
-mod utils;
-mod decoding;
-
-use utils::RawImage;
-use decoding::get_image;
+use oxydicom::{ModalityLut, RawImage, get_frames, open_dicom};
 
 fn main() -> Result<()> {
 
@@ -30,25 +25,34 @@ fn main() -> Result<()> {
                 .to_str().ok_or(anyhow!("Not unicode"))?;
 
             let t0 = Instant::now();
-            let dicom = open_file(dicom_path.as_os_str())?;
+            let dicom = open_dicom(&dicom_path)?;
             let t1 = Instant::now();
-            let decoded_image_data = get_image(&dicom)?;
-            let RawImage { bytes, .. } = decoded_image_data;
+            let frames = get_frames(&dicom)?;
+            let nb_bytes: usize = frames.iter().map(|RawImage { bytes, .. }| bytes.len()).sum();
             let t2 = Instant::now();
+            let modality_lut = ModalityLut::from_dicom(&dicom)?;
+            for frame in frames.iter() {
+                modality_lut.apply(frame)?;
+            }
+            let t3 = Instant::now();
 
             let dt1: i32 = (t1 - t0).as_millis().try_into()?;
             let dt2: i32 = (t2 - t1).as_millis().try_into()?;
+            let dt3: i32 = (t3 - t2).as_millis().try_into()?;
 
             let dt1: f64 = dt1.try_into()?;
             let dt2: f64 = dt2.try_into()?;
+            let dt3: f64 = dt3.try_into()?;
 
             let dt1: f64 = dt1 / 1000.0;
             let dt2: f64 = dt2 / 1000.0;
+            let dt3: f64 = dt3 / 1000.0;
 
             println!("  {}", filename);
-            println!("    {} bytes", bytes.len());
+            println!("    {} frames, {} bytes", frames.len(), nb_bytes);
             println!("    Opening  : {}s", dt1);
             println!("    Decoding : {}s", dt2);
+            println!("    Modality : {}s", dt3);
         }
     }
 
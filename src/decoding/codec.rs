@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, OnceLock, RwLock};
+use crate::utils::{ColorSpace, Format};
+use super::error::Result;
+
+
+/// Decoder of the encapsulated frames of a transfer syntax.
+/// Codecs are registered by transfer syntax UID with `register_codec`.
+pub trait PixelCodec: Send + Sync {
+
+    /// Name used in error messages
+    fn name(&self) -> &str;
+
+    /// Decodes one frame to interleaved little endian samples of `format`
+    /// (`channel_depth` bytes per sample).
+    fn decode(&self, bytes: &[u8], format: &Format) -> Result<Vec<u8>>;
+
+    /// Whether decoded samples keep all their allocated bits, like native data.
+    /// The bits outside of Bits Stored are then masked.
+    fn keeps_allocated_bits(&self) -> bool {
+        false
+    }
+
+    /// Whether 3-channel samples are already converted to RGB by the codec
+    /// for this photometric interpretation.
+    fn converts_color(&self, _color_space: ColorSpace) -> bool {
+        false
+    }
+}
+
+impl fmt::Debug for dyn PixelCodec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+type Registry = HashMap<String, Arc<dyn PixelCodec>>;
+
+fn registry() -> &'static RwLock<Registry> {
+    static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(builtin_codecs()))
+}
+
+/// Registers `codec` for the transfer syntax `uid`, replacing the codec
+/// previously registered for it, built-in codecs included.
+pub fn register_codec(uid: &str, codec: Arc<dyn PixelCodec>) {
+    registry().write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(normalize_uid(uid).to_owned(), codec);
+}
+
+/// Codec registered for the transfer syntax `uid`
+pub fn get_codec(uid: &str) -> Option<Arc<dyn PixelCodec>> {
+    registry().read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(normalize_uid(uid))
+        .cloned()
+}
+
+/// UIDs are padded to an even length with a null byte, some writers use spaces
+pub(super) fn normalize_uid(uid: &str) -> &str {
+    uid.trim_end_matches(['\0', ' '])
+}
+
+
+/* Built-in codecs */
+
+fn builtin_codecs() -> Registry {
+
+    let rle: Arc<dyn PixelCodec> = Arc::new(RLECodec);
+    let jpeg: Arc<dyn PixelCodec> = Arc::new(JPEGCodec);
+    let jpeg_lossless: Arc<dyn PixelCodec> = Arc::new(JPEGLosslessCodec);
+    let jpeg_ls: Arc<dyn PixelCodec> = Arc::new(JPEGLSCodec);
+    let jpeg_2000: Arc<dyn PixelCodec> = Arc::new(JPEG2000Codec);
+    let htj2k: Arc<dyn PixelCodec> = Arc::new(HTJ2KCodec);
+
+    [
+        ("1.2.840.10008.1.2.4.50", &jpeg),
+        ("1.2.840.10008.1.2.4.51", &jpeg),
+        ("1.2.840.10008.1.2.4.57", &jpeg_lossless),
+        ("1.2.840.10008.1.2.4.70", &jpeg_lossless),
+        ("1.2.840.10008.1.2.4.80", &jpeg_ls),
+        ("1.2.840.10008.1.2.4.81", &jpeg_ls),
+        ("1.2.840.10008.1.2.4.90", &jpeg_2000),
+        ("1.2.840.10008.1.2.4.91", &jpeg_2000),
+        ("1.2.840.10008.1.2.4.201", &htj2k),
+        ("1.2.840.10008.1.2.4.202", &htj2k),
+        ("1.2.840.10008.1.2.4.203", &htj2k),
+        ("1.2.840.10008.1.2.5", &rle)
+    ].iter()
+        .map(|(uid, codec)| (uid.to_string(), Arc::clone(codec)))
+        .collect()
+}
+
+struct RLECodec;
+
+impl PixelCodec for RLECodec {
+    fn name(&self) -> &str { "RLE" }
+    fn decode(&self, bytes: &[u8], format: &Format) -> Result<Vec<u8>> { super::decode_RLE(bytes, format) }
+    // Segments hold whole samples
+    fn keeps_allocated_bits(&self) -> bool { true }
+}
+
+struct JPEGCodec;
+
+impl PixelCodec for JPEGCodec {
+    fn name(&self) -> &str { "JPEG" }
+    fn decode(&self, bytes: &[u8], format: &Format) -> Result<Vec<u8>> { super::decode_JPEG(bytes, format) }
+    // The JPEG decoders output RGB
+    fn converts_color(&self, _color_space: ColorSpace) -> bool { true }
+}
+
+struct JPEGLosslessCodec;
+
+impl PixelCodec for JPEGLosslessCodec {
+    fn name(&self) -> &str { "JPEG Lossless" }
+    fn decode(&self, bytes: &[u8], format: &Format) -> Result<Vec<u8>> { super::decode_JPEG_lossless(bytes, format) }
+}
+
+struct JPEGLSCodec;
+
+impl PixelCodec for JPEGLSCodec {
+    fn name(&self) -> &str { "JPEG-LS" }
+    fn decode(&self, bytes: &[u8], format: &Format) -> Result<Vec<u8>> { super::decode_JPEG_LS(bytes, format) }
+}
+
+// OpenJPEG reverses the ICT and RCT transforms
+struct JPEG2000Codec;
+
+impl PixelCodec for JPEG2000Codec {
+    fn name(&self) -> &str { "JPEG2000" }
+    fn decode(&self, bytes: &[u8], format: &Format) -> Result<Vec<u8>> { super::decode_JPEG2000(bytes, format) }
+    fn converts_color(&self, color_space: ColorSpace) -> bool {
+        matches!(color_space, ColorSpace::YBRICT | ColorSpace::YBRRCT)
+    }
+}
+
+struct HTJ2KCodec;
+
+impl PixelCodec for HTJ2KCodec {
+    fn name(&self) -> &str { "HTJ2K" }
+    fn decode(&self, bytes: &[u8], format: &Format) -> Result<Vec<u8>> { super::decode_HTJ2K(bytes, format) }
+    fn converts_color(&self, color_space: ColorSpace) -> bool {
+        matches!(color_space, ColorSpace::YBRICT | ColorSpace::YBRRCT)
+    }
+}
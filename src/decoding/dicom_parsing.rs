@@ -1,8 +1,246 @@
-use dicom::object::Tag;
-use dicom::core::value::{Value, PrimitiveValue};
-use anyhow::{Context, Result, bail, ensure};
+use std::convert::{TryFrom, TryInto};
+use std::path::Path;
+use dicom::object::{Tag, FileMetaTable, StandardDataDictionary};
+use dicom::core::value::{DicomValueType, Value, PrimitiveValue};
+use dicom::encoding::TransferSyntax;
+use dicom::encoding::transfer_syntax::TransferSyntaxIndex;
+use dicom::transfer_syntax::TransferSyntaxRegistry;
+use crate::utils::{ColorSpace, EncodedImage, Encoding, Dicom, DicomItem, Format, Palettes};
+use super::error::{DecodeError, Result, error_chain};
+use super::lut::Lut;
+use super::codec::{get_codec, normalize_uid};
 
-use crate::utils::{EncodedImage, Encoding, Dicom, Format, Palettes};
+
+const IMPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2";
+const EXPLICIT_VR_BIG_ENDIAN: &str = "1.2.840.10008.1.2.2";
+const EXPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2.1";
+const DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2.1.99";
+const JPEG_2000: &str = "1.2.840.10008.1.2.4.90";
+
+
+pub fn open_dicom(path: &Path) -> Result<Dicom> {
+
+    let mut file_bytes = std::fs::read(path)?;
+
+    if file_bytes.len() <= 128 {
+        return Err(DecodeError::InvalidFile("missing preamble".to_owned()))
+    }
+
+    /*
+        File meta information
+    */
+
+    check_file_meta_group(&file_bytes[128..])?;
+
+    let mut reader = &file_bytes[128..];
+    let meta = FileMetaTable::from_reader(&mut reader)
+        .map_err(|e| DecodeError::InvalidFile(format!("file meta information: {}", error_chain(&e))))?;
+    let meta_end = file_bytes.len() - reader.len();
+
+    /*
+        Data set
+    */
+
+    let uid = normalize_uid(&meta.transfer_syntax);
+
+    let syntax_index = SyntaxIndex::default();
+    if syntax_index.get(uid).is_none() {
+        return Err(DecodeError::UnsupportedTransferSyntax(uid.to_owned()))
+    }
+
+    // The data set is read in place, or inflated after the file meta information
+    if uid == DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN {
+        let deflated = file_bytes.split_off(meta_end);
+        inflate_data_set(&deflated, &mut file_bytes)?;
+    }
+
+    match uid {
+        IMPLICIT_VR_LITTLE_ENDIAN | EXPLICIT_VR_BIG_ENDIAN => (),
+        _ => relabel_extended_offset_table(&mut file_bytes[meta_end..])
+    }
+
+    let dicom = Dicom::from_reader_with(&file_bytes[128..], StandardDataDictionary, syntax_index)
+        .map_err(|e| DecodeError::InvalidFile(error_chain(&e)))?;
+
+    Ok(dicom)
+}
+
+/// Deflated data sets are inflated by chunks, up to `MAX_INFLATED_SIZE` bytes:
+/// a few kilobytes of deflated data can expand to gigabytes.
+fn inflate_data_set(deflated: &[u8], output: &mut Vec<u8>) -> Result<()> {
+
+    use miniz_oxide::inflate::stream::{inflate, InflateState};
+    use miniz_oxide::{DataFormat, MZFlush, MZStatus};
+
+    const MAX_INFLATED_SIZE: usize = 1 << 30;
+
+    let invalid = |message: String| DecodeError::InvalidFile(format!("cannot inflate data set: {}", message));
+
+    let mut state = InflateState::new_boxed(DataFormat::Raw);
+    let mut chunk = vec![0; 1 << 16];
+    let mut input = deflated;
+    let mut inflated_size = 0;
+
+    loop {
+        let result = inflate(&mut state, input, &mut chunk, MZFlush::None);
+        input = &input[result.bytes_consumed..];
+        inflated_size += result.bytes_written;
+
+        if inflated_size > MAX_INFLATED_SIZE {
+            return Err(invalid(format!("more than {} bytes", MAX_INFLATED_SIZE)))
+        }
+        output.extend_from_slice(&chunk[..result.bytes_written]);
+
+        match result.status {
+            Ok(MZStatus::StreamEnd) => return Ok(()),
+            Ok(_) if result.bytes_consumed > 0 || result.bytes_written > 0 => (),
+            Ok(status) => return Err(invalid(format!("{:?}", status))),
+            Err(error) => return Err(invalid(format!("{:?}", error)))
+        }
+    }
+}
+
+/// dicom-rs counts the File Meta Information Group Length down by the size it expects
+/// for each element, and overflows when an element is unknown, has another VR or
+/// crosses the group length. The group must be made of the standard elements and
+/// fill the group length exactly.
+fn check_file_meta_group(bytes: &[u8]) -> Result<()> {
+
+    let invalid = || DecodeError::InvalidFile("bad file meta information group".to_owned());
+
+    // DICM, then (0002,0000) UL with a 2-byte length of 4
+    let header = bytes.get(..16).ok_or_else(invalid)?;
+    if header[..12] != *b"DICM\x02\x00\x00\x00UL\x04\x00" {
+        return Ok(()) // Reported by dicom-rs
+    }
+    let group_length = u32::from_le_bytes([header[12], header[13], header[14], header[15]]) as usize;
+    let group = bytes.get(16..16 + group_length).ok_or_else(invalid)?;
+
+    let mut pos = 0;
+
+    while pos < group.len() {
+
+        let element = group.get(pos..pos + 8).ok_or_else(invalid)?;
+        let tag = Tag(u16::from_le_bytes([element[0], element[1]]), u16::from_le_bytes([element[2], element[3]]));
+
+        // Information Version and Private Information are OB, the others are strings
+        let long_header = match tag {
+            Tag(0x0002, 0x0001) | Tag(0x0002, 0x0102) => true,
+            Tag(0x0002, 0x0002) | Tag(0x0002, 0x0003) | Tag(0x0002, 0x0010) | Tag(0x0002, 0x0012)
+            | Tag(0x0002, 0x0013) | Tag(0x0002, 0x0016..=0x0018) | Tag(0x0002, 0x0100) => false,
+            _ => return Err(invalid())
+        };
+
+        if long_header != (&element[4..6] == b"OB") {
+            return Err(invalid())
+        }
+
+        pos += match long_header {
+            true => {
+                let len = group.get(pos + 8..pos + 12).ok_or_else(invalid)?;
+                12 + u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize
+            },
+            false => 8 + usize::from(u16::from_le_bytes([element[6], element[7]]))
+        };
+    }
+
+    match pos == group.len() {
+        true => Ok(()),
+        false => Err(invalid())
+    }
+}
+
+/// dicom-rs reads OV headers with a 2-byte length, which breaks on the Extended
+/// Offset Table and its lengths. They are relabelled OB, which has the same header
+/// layout, and read back as raw bytes. The top level elements of the explicit VR
+/// little endian data set are walked up to the Pixel Data.
+fn relabel_extended_offset_table(dataset_bytes: &mut [u8]) {
+
+    let mut pos = 0;
+
+    while let Some(header) = dataset_bytes.get(pos..pos + 8) {
+
+        let tag = Tag(u16::from_le_bytes([header[0], header[1]]), u16::from_le_bytes([header[2], header[3]]));
+
+        if tag >= Tag(0x7FE0, 0x0010) {
+            break
+        }
+        if (tag == Tag(0x7FE0, 0x0001) || tag == Tag(0x7FE0, 0x0002)) && &header[4..6] == b"OV" {
+            dataset_bytes[pos + 5] = b'B';
+        }
+
+        pos = match skip_element(dataset_bytes, pos, 0) {
+            Some(next) => next,
+            None => break // Reported by dicom-rs
+        };
+    }
+}
+
+/// Position following the explicit VR little endian element at `pos`
+fn skip_element(bytes: &[u8], pos: usize, depth: usize) -> Option<usize> {
+
+    let header = bytes.get(pos..pos + 8)?;
+
+    let long_header = matches!(&header[4..6],
+        b"OB" | b"OD" | b"OF" | b"OL" | b"OV" | b"OW" | b"SQ" | b"SV" | b"UC" | b"UN" | b"UR" | b"UT" | b"UV");
+
+    if !long_header {
+        return Some(pos + 8 + usize::from(u16::from_le_bytes([header[6], header[7]])))
+    }
+
+    let len = bytes.get(pos + 8..pos + 12)?;
+    let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]);
+
+    match len {
+        u32::MAX => skip_items(bytes, pos + 12, depth + 1),
+        len => (pos + 12).checked_add(usize::try_from(len).ok()?)
+    }
+}
+
+/// Position following the delimitation of a sequence of undefined length
+fn skip_items(bytes: &[u8], mut pos: usize, depth: usize) -> Option<usize> {
+
+    // Nested sequences are walked recursively
+    if depth > 64 {
+        return None
+    }
+
+    loop {
+        let header = bytes.get(pos..pos + 8)?;
+        let tag = Tag(u16::from_le_bytes([header[0], header[1]]), u16::from_le_bytes([header[2], header[3]]));
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        pos += 8;
+
+        match (tag, len) {
+            (Tag(0xFFFE, 0xE0DD), _) => return Some(pos),
+            (Tag(0xFFFE, 0xE000), u32::MAX) => {
+                while bytes.get(pos..pos + 4)? != [0xFE, 0xFF, 0x0D, 0xE0] {
+                    pos = skip_element(bytes, pos, depth)?;
+                }
+                pos += 8;
+            },
+            (Tag(0xFFFE, 0xE000), len) => pos = pos.checked_add(usize::try_from(len).ok()?)?,
+            _ => return None
+        }
+    }
+}
+
+/// The transfer syntax registry of dicom-rs, with the syntaxes it cannot
+/// read data sets for mapped to ones it can.
+#[derive(Default)]
+struct SyntaxIndex(TransferSyntaxRegistry);
+
+impl TransferSyntaxIndex for SyntaxIndex {
+    fn get(&self, uid: &str) -> Option<&TransferSyntax> {
+        match normalize_uid(uid) {
+            // The data set has been inflated by `open_dicom`
+            DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN => self.0.get(EXPLICIT_VR_LITTLE_ENDIAN),
+            // Registered codecs dicom-rs does not know (HTJ2K, proprietary syntaxes...),
+            // the data set is encoded like JPEG 2000: explicit VR little endian, encapsulated
+            uid => self.0.get(uid).or_else(|| get_codec(uid).and_then(|_| self.0.get(JPEG_2000)))
+        }
+    }
+}
 
 
 pub fn get_encoded_image_data(dicom: &Dicom) -> Result<EncodedImage> {
@@ -11,58 +249,180 @@ pub fn get_encoded_image_data(dicom: &Dicom) -> Result<EncodedImage> {
         Dimensions
     */
 
-    let w = dicom.element(Tag(0x0028, 0x0011))?.to_int()?;
-    let h = dicom.element(Tag(0x0028, 0x0010))?.to_int()?;
-    let bits_per_sample: u32 = dicom.element(Tag(0x0028, 0x0100))?.to_int()?;
-    let samples_per_pixel = dicom.element(Tag(0x0028, 0x0002))?.to_int()?;
+    // Rows and Columns are 16-bit values, frame sizes cannot overflow
+    let w = u32::from(get_int::<u16>(dicom, Tag(0x0028, 0x0011))?);
+    let h = u32::from(get_int::<u16>(dicom, Tag(0x0028, 0x0010))?);
+    let samples_per_pixel: u32 = get_int(dicom, Tag(0x0028, 0x0002))?;
+
+    if w == 0 || h == 0 {
+        return Err(DecodeError::dimensions(format!("Empty image: {}x{}", w, h)))
+    }
+    if samples_per_pixel == 0 || samples_per_pixel > 4 {
+        return Err(DecodeError::invalid_attribute(Tag(0x0028, 0x0002), format!("{} samples per pixel", samples_per_pixel)))
+    }
+
+    /*
+        Sample bits
+    */
+
+    let bits_allocated: u32 = get_int(dicom, Tag(0x0028, 0x0100))?;
+    let bits_stored: u32 = get_opt_int(dicom, Tag(0x0028, 0x0101))?.unwrap_or(bits_allocated);
+    let high_bit: u32 = get_opt_int(dicom, Tag(0x0028, 0x0102))?.unwrap_or_else(|| bits_stored.saturating_sub(1));
+
+    if bits_allocated == 0 || (bits_allocated > 16 && !bits_allocated.is_multiple_of(8)) || bits_allocated > 64 {
+        return Err(DecodeError::invalid_attribute(Tag(0x0028, 0x0100), format!("{} bits allocated", bits_allocated)))
+    }
+    if bits_stored == 0 || bits_stored > bits_allocated || high_bit >= bits_allocated || high_bit + 1 < bits_stored {
+        return Err(DecodeError::invalid_attribute(Tag(0x0028, 0x0101), format!(
+            "bits stored / high bit: {} / {} for {} bits allocated", bits_stored, high_bit, bits_allocated
+        )))
+    }
 
-    ensure!(bits_per_sample % 8 == 0, "Bad bits_per_sample: {}", bits_per_sample);
-    let bytes_per_sample = bits_per_sample / 8;
+    let pixel_representation: u32 = get_opt_int(dicom, Tag(0x0028, 0x0103))?.unwrap_or(0);
+
+    if pixel_representation > 1 {
+        return Err(DecodeError::invalid_attribute(Tag(0x0028, 0x0103), pixel_representation.to_string()))
+    }
+
+    // Packed samples (1-bit, 12-bit...) are unpacked to whole bytes
+    let packed = !bits_allocated.is_multiple_of(8);
+    let bytes_per_sample = bits_allocated.div_ceil(8);
 
     /*
         Encoding
     */
 
-    let uid = dicom.meta().transfer_syntax.as_str();
-    let uid = uid.trim_end_matches(char::from(0)); // Get rid of null terminators
+    let uid = normalize_uid(&dicom.meta().transfer_syntax);
 
     let encoding = match uid {
+        "1.2.840.10008.1.2"         => Encoding::RAW,
         "1.2.840.10008.1.2.1"       => Encoding::RAW,
-        "1.2.840.10008.1.2.4.50"    => Encoding::JPEG,
-        "1.2.840.10008.1.2.4.90"    => Encoding::JPEG2000,
-        "1.2.840.10008.1.2.5"       => Encoding::RLE,
-        val => bail!("Unhandled transfer syntax: {}", val)
+        "1.2.840.10008.1.2.1.99"    => Encoding::RAW, // Data set already inflated by open_dicom
+        "1.2.840.10008.1.2.2"       => Encoding::RAWBigEndian,
+        uid => match get_codec(uid) {
+            Some(codec) => Encoding::Encapsulated(codec),
+            None => return Err(DecodeError::UnsupportedTransferSyntax(uid.to_owned()))
+        }
     };
 
     /*
-        Pixel data
+        Photometric interpretation
     */
 
-    let pixel_bytes = match dicom.element(Tag(0x7FE0, 0x0010))?.value() {
+    let photo_interp_str = get_photometric_interpretation(dicom)?;
 
-        Value::Primitive(PrimitiveValue::U8(pixel_bytes)) => pixel_bytes.as_slice(),
-        Value::Primitive(PrimitiveValue::U16(_)) => unimplemented!(),
-        Value::PixelSequence { fragments, .. } => {
-            let frag = fragments.as_slice();
-            &frag[0]
-        }
+    let palettes = match photo_interp_str.as_ref() {
+        "PALETTECOLOR" => Some(get_palettes(dicom, pixel_representation == 1)?),
+        _ => None
+    };
 
-        val => bail!("Unexpected pixel data type: {:?}", val)
+    let color_space = match photo_interp_str.as_ref() {
+        "YBR_FULL" => ColorSpace::YBRFull,
+        "YBR_FULL_422" => ColorSpace::YBRFull422,
+        "YBR_PARTIAL_422" => ColorSpace::YBRPartial422,
+        "YBR_ICT" => ColorSpace::YBRICT,
+        "YBR_RCT" => ColorSpace::YBRRCT,
+        _ => ColorSpace::RGB
     };
 
-    let pixel_bytes = pixel_bytes.to_vec();
+    let planar_configuration: u32 = get_opt_int(dicom, Tag(0x0028, 0x0006))?.unwrap_or(0);
+
+    if planar_configuration > 1 {
+        return Err(DecodeError::invalid_attribute(Tag(0x0028, 0x0006), planar_configuration.to_string()))
+    }
 
     /*
-        Photometric interpretation
+        Pixel data
     */
 
-    let photo_interp_str = dicom.element(Tag(0x0028, 0x0004))?.to_str()?;
-    let mut photo_interp_str = photo_interp_str.trim_end_matches(char::from(0)).to_owned(); // Get rid of null terminators
-    photo_interp_str.retain(|c| !c.is_whitespace()); // Get rid of whitespaces
+    let nb_frames: usize = get_opt_int(dicom, Tag(0x0028, 0x0008))?.unwrap_or(1);
 
-    let palettes = match photo_interp_str.as_ref() {
-        "PALETTECOLOR" => Some(get_palettes(dicom)?),
-        _ => None
+    if nb_frames == 0 {
+        return Err(DecodeError::invalid_attribute(Tag(0x0028, 0x0008), "0 frames"))
+    }
+
+    // Native 4:2:2 data holds two luminance and two chroma samples per pair of pixels
+    let native_422 = matches!(color_space, ColorSpace::YBRFull422 | ColorSpace::YBRPartial422)
+        && matches!(encoding, Encoding::RAW | Encoding::RAWBigEndian);
+
+    let samples_per_frame: usize = match native_422 {
+        true => {
+            if samples_per_pixel != 3 || !w.is_multiple_of(2) {
+                return Err(DecodeError::dimensions(format!(
+                    "Bad 4:2:2 image: {}x{} with {} samples per pixel", w, h, samples_per_pixel
+                )))
+            }
+            (u64::from(w) * u64::from(h) * 2).try_into()?
+        },
+        false => (u64::from(w) * u64::from(h) * u64::from(samples_per_pixel)).try_into()?
+    };
+
+    // Codecs always output interleaved samples, RLE segments are interleaved by our decoder
+    // and 4:2:2 data is interleaved by definition
+    let planar = planar_configuration == 1 && samples_per_pixel > 1 && !native_422
+        && matches!(encoding, Encoding::RAW | Encoding::RAWBigEndian);
+
+    // Float Pixel Data and Double Float Pixel Data hold IEEE 754 samples
+    let (pixel_data, float) = match dicom.element(Tag(0x7FE0, 0x0010)) {
+        Ok(elem) => (elem, false),
+        Err(_) => {
+            let (elem, float_bits) = match dicom.element(Tag(0x7FE0, 0x0008)) {
+                Ok(elem) => (elem, 32),
+                Err(_) => match dicom.element(Tag(0x7FE0, 0x0009)) {
+                    Ok(elem) => (elem, 64),
+                    Err(_) => return Err(DecodeError::MissingAttribute(Tag(0x7FE0, 0x0010)))
+                }
+            };
+            if bits_allocated != float_bits {
+                return Err(DecodeError::invalid_attribute(Tag(0x0028, 0x0100), format!(
+                    "{} bits allocated for {}-bit float pixel data", bits_allocated, float_bits
+                )))
+            }
+            if !matches!(encoding, Encoding::RAW | Encoding::RAWBigEndian) {
+                return Err(DecodeError::unsupported("Float pixel data must be native"))
+            }
+            (elem, true)
+        }
+    };
+
+    let frames = match pixel_data.value() {
+
+        Value::Primitive(PrimitiveValue::U8(pixel_bytes)) => {
+            split_native_frames(pixel_bytes, bits_allocated, samples_per_frame, nb_frames)?
+        },
+
+        // With Implicit VR, the VR of Pixel Data comes from the dictionary and is
+        // always OW, so the parser hands us 16-bit words instead of raw bytes. Other
+        // VRs (OL, OF, OD...) give other numeric values.
+        // Values are written back in the byte order of the file, big endian samples
+        // are swapped during decoding. Packed samples fill the words from the least
+        // significant bit, whatever the byte order.
+        Value::Primitive(value) => {
+            let big_endian = matches!(encoding, Encoding::RAWBigEndian) && !packed;
+            let pixel_bytes = numeric_to_bytes(value, big_endian)
+                .ok_or_else(|| DecodeError::invalid_attribute(
+                    Tag(0x7FE0, 0x0010), format!("unexpected type {:?}", value.value_type())
+                ))?;
+            split_native_frames(&pixel_bytes, bits_allocated, samples_per_frame, nb_frames)?
+        },
+
+        Value::PixelSequence { offset_table, fragments } => {
+            if packed {
+                return Err(DecodeError::unsupported(format!(
+                    "Bits allocated {} is only supported for native pixel data", bits_allocated
+                )))
+            }
+            let extended_offset_table = get_extended_offset_table(dicom)?;
+            split_encapsulated_frames(offset_table, fragments, extended_offset_table, nb_frames)?
+        }
+
+        Value::Sequence { .. } => return Err(DecodeError::invalid_attribute(Tag(0x7FE0, 0x0010), "unexpected sequence"))
+    };
+
+    // Unpacked samples are little endian
+    let encoding = match packed {
+        true => Encoding::RAW,
+        false => encoding
     };
 
     Ok(EncodedImage { 
@@ -70,30 +430,309 @@ pub fn get_encoded_image_data(dicom: &Dicom) -> Result<EncodedImage> {
         target_format: Format {
             w, h,
             channels: samples_per_pixel,
-            channel_depth: bytes_per_sample
+            channel_depth: bytes_per_sample,
+            signed: pixel_representation == 1 && !float,
+            float
         },
         encoding,
         palettes,
-        bytes: pixel_bytes
+        color_space,
+        planar,
+        bits_stored,
+        high_bit,
+        frames
     })
 }
 
-fn get_palettes(dicom: &Dicom) -> Result<Palettes> {
+/// Bytes of binary numeric values, None for strings, dates...
+fn numeric_to_bytes(value: &PrimitiveValue, big_endian: bool) -> Option<Vec<u8>> {
 
-    const TAGS_MAP: [Tag; 3] = [
-        Tag(0x0028, 0x1201), // RED
-        Tag(0x0028, 0x1202), // GREEN
-        Tag(0x0028, 0x1203)  // BLUE
-    ];
+    let (values, size): (Vec<u64>, usize) = match value {
+        PrimitiveValue::U16(values) => (values.iter().map(|&v| u64::from(v)).collect(), 2),
+        PrimitiveValue::I16(values) => (values.iter().map(|&v| u64::from(v as u16)).collect(), 2),
+        PrimitiveValue::U32(values) => (values.iter().map(|&v| u64::from(v)).collect(), 4),
+        PrimitiveValue::I32(values) => (values.iter().map(|&v| u64::from(v as u32)).collect(), 4),
+        PrimitiveValue::F32(values) => (values.iter().map(|&v| u64::from(v.to_bits())).collect(), 4),
+        PrimitiveValue::U64(values) => (values.to_vec(), 8),
+        PrimitiveValue::I64(values) => (values.iter().map(|&v| v as u64).collect(), 8),
+        PrimitiveValue::F64(values) => (values.iter().map(|&v| v.to_bits()).collect(), 8),
+        _ => return None
+    };
+
+    let bytes = values
+        .iter()
+        .flat_map(|v| match big_endian {
+            true => v.to_be_bytes()[8 - size..].to_vec(),
+            false => v.to_le_bytes()[..size].to_vec()
+        })
+        .collect();
+
+    Some(bytes)
+}
+
+/// Native frames are stored back to back, the pixel data may end with a padding byte.
+/// When Bits Allocated is not a multiple of 8, samples are packed without gaps, even
+/// across frames.
+fn split_native_frames(
+    pixel_bytes: &[u8],
+    bits_allocated: u32,
+    samples_per_frame: usize,
+    nb_frames: usize
+) -> Result<Vec<Vec<u8>>> {
 
-    TAGS_MAP.iter()
-        .copied()
-        .map(|tag| {
-            let elem = dicom.element(tag)
-                .context("Missing palette data element")?;
-            let palette = elem.to_multi_int()
-                .context("Unexpected palette data type")?;
-            Ok(palette)
+    let frame_bits = samples_per_frame
+        .checked_mul(bits_allocated as usize)
+        .filter(|frame_bits| {
+            frame_bits.checked_mul(nb_frames).is_some_and(|total_bits| pixel_bytes.len() * 8 >= total_bits)
         })
-        .collect()
-} 
+        .ok_or_else(|| DecodeError::dimensions(format!(
+            "Pixel data too short: {} bytes for {} frames of {} samples of {} bits",
+            pixel_bytes.len(), nb_frames, samples_per_frame, bits_allocated
+        )))?;
+
+    let frames = match bits_allocated % 8 {
+
+        0 => pixel_bytes
+            .chunks_exact(frame_bits / 8)
+            .take(nb_frames)
+            .map(|frame| frame.to_vec())
+            .collect(),
+
+        _ => (0..nb_frames)
+            .map(|i| unpack_samples(pixel_bytes, i * frame_bits, samples_per_frame, bits_allocated))
+            .collect()
+    };
+
+    Ok(frames)
+}
+
+/// Reads packed samples, least significant bit first, into 8 or 16-bit little endian samples
+fn unpack_samples(bytes: &[u8], first_bit: usize, nb_samples: usize, bits: u32) -> Vec<u8> {
+
+    let mask = (1u32 << bits) - 1;
+    let depth = bits.div_ceil(8) as usize;
+
+    let mut samples = Vec::with_capacity(nb_samples * depth);
+
+    for k in 0..nb_samples {
+
+        let bit = first_bit + k * bits as usize;
+
+        // A sample of up to 16 bits spans at most 3 bytes
+        let word = bytes[bit / 8..]
+            .iter()
+            .take(3)
+            .enumerate()
+            .fold(0u32, |word, (j, byte)| word | u32::from(*byte) << (8 * j));
+
+        let value = (word >> (bit % 8)) & mask;
+        samples.extend_from_slice(&value.to_le_bytes()[..depth]);
+    }
+
+    samples
+}
+
+/// Extended Offset Table (7FE0,0001) and its lengths (7FE0,0002), if present.
+fn get_extended_offset_table(dicom: &Dicom) -> Result<Option<(Vec<u64>, Vec<u64>)>> {
+
+    let offsets = match dicom.element(Tag(0x7FE0, 0x0001)) {
+        Ok(elem) => get_u64_values(elem.value().primitive(), Tag(0x7FE0, 0x0001))?,
+        Err(_) => return Ok(None)
+    };
+
+    let lengths = dicom.element(Tag(0x7FE0, 0x0002))
+        .map_err(|_| DecodeError::MissingAttribute(Tag(0x7FE0, 0x0002)))?;
+    let lengths = get_u64_values(lengths.value().primitive(), Tag(0x7FE0, 0x0002))?;
+
+    if offsets.len() != lengths.len() {
+        return Err(DecodeError::corrupt(format!(
+            "Extended offset table has {} offsets and {} lengths", offsets.len(), lengths.len()
+        )))
+    }
+
+    Ok(Some((offsets, lengths)))
+}
+
+fn get_u64_values(value: Option<&PrimitiveValue>, tag: Tag) -> Result<Vec<u64>> {
+    match value {
+        Some(PrimitiveValue::U64(values)) => Ok(values.to_vec()),
+        // Unknown VR, raw little endian bytes
+        Some(PrimitiveValue::U8(bytes)) if bytes.len().is_multiple_of(8) => Ok(bytes
+            .chunks_exact(8)
+            .map(|b| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+            .collect()),
+        val => Err(DecodeError::invalid_attribute(tag, format!("unexpected type {:?}", val.map(|v| v.value_type()))))
+    }
+}
+
+/// Groups the fragments of encapsulated pixel data into frames. Offsets of both tables
+/// are counted from the item header of the first fragment.
+fn split_encapsulated_frames(
+    offset_table: &[u8],
+    fragments: &[Vec<u8>],
+    extended_offset_table: Option<(Vec<u64>, Vec<u64>)>,
+    nb_frames: usize
+) -> Result<Vec<Vec<u8>>> {
+
+    let mut positions = Vec::with_capacity(fragments.len());
+    let mut position = 0u64;
+    for fragment in fragments.iter() {
+        positions.push(position);
+        position += 8 + fragment.len() as u64;
+    }
+
+    let fragment_at = |offset: u64| positions
+        .binary_search(&offset)
+        .map_err(|_| DecodeError::corrupt(format!("Offset table entry {} does not point to a fragment", offset)));
+
+    let frames: Vec<Vec<u8>> = match extended_offset_table {
+
+        // Each frame is a single fragment
+        Some((offsets, lengths)) => offsets.iter()
+            .zip(lengths.iter())
+            .map(|(offset, length)| {
+                let fragment = &fragments[fragment_at(*offset)?];
+                let length = (*length).try_into().unwrap_or(usize::MAX).min(fragment.len());
+                Ok(fragment[..length].to_vec())
+            })
+            .collect::<Result<_>>()?,
+
+        None if !offset_table.is_empty() => {
+
+            if !offset_table.len().is_multiple_of(4) {
+                return Err(DecodeError::corrupt(format!("Bad basic offset table length: {}", offset_table.len())))
+            }
+
+            let starts = offset_table
+                .chunks_exact(4)
+                .map(|b| fragment_at(u32::from_le_bytes([b[0], b[1], b[2], b[3]]).into()))
+                .collect::<Result<Vec<usize>>>()?;
+
+            starts.iter()
+                .enumerate()
+                .map(|(i, start)| {
+                    let end = starts.get(i + 1).copied().unwrap_or(fragments.len());
+                    if *start >= end {
+                        return Err(DecodeError::corrupt("Basic offset table is not increasing"))
+                    }
+                    Ok(fragments[*start..end].concat())
+                })
+                .collect::<Result<_>>()?
+        },
+
+        None if nb_frames == 1 => vec![fragments.concat()],
+
+        None if fragments.len() == nb_frames => fragments.to_vec(),
+
+        None => split_at_end_markers(fragments)
+    };
+
+    if frames.len() != nb_frames {
+        return Err(DecodeError::dimensions(format!(
+            "Found {} frames in encapsulated pixel data, expected {}", frames.len(), nb_frames
+        )))
+    }
+
+    Ok(frames)
+}
+
+/// Without offset table, a frame ends with the last marker of its codestream:
+/// EOI for JPEG and JPEG-LS, EOC for JPEG 2000, which are both 0xFFD9.
+fn split_at_end_markers(fragments: &[Vec<u8>]) -> Vec<Vec<u8>> {
+
+    let mut frames = Vec::new();
+    let mut frame = Vec::new();
+
+    for fragment in fragments.iter() {
+
+        frame.extend_from_slice(fragment);
+
+        // Fragments have an even length, the marker may be followed by a padding byte
+        let end = match fragment.last() {
+            Some(0) => &fragment[..fragment.len() - 1],
+            _ => fragment.as_slice()
+        };
+
+        if end.ends_with(&[0xFF, 0xD9]) {
+            frames.push(std::mem::take(&mut frame));
+        }
+    }
+
+    if !frame.is_empty() {
+        frames.push(frame);
+    }
+
+    frames
+}
+
+/// Photometric interpretation without spaces, e.g. "PALETTECOLOR"
+pub fn get_photometric_interpretation(dicom: &Dicom) -> Result<String> {
+
+    let mut photo_interp_str = get_str(dicom, Tag(0x0028, 0x0004))?
+        .ok_or(DecodeError::MissingAttribute(Tag(0x0028, 0x0004)))?;
+    photo_interp_str.retain(|c| !c.is_whitespace()); // Get rid of whitespaces
+
+    Ok(photo_interp_str)
+}
+
+/// MONOCHROME1 images and Presentation LUT Shape (2050,0020) INVERSE are displayed
+/// with the lowest values in white. DX images carry both, they are not inverted twice.
+pub fn is_inverted(dicom: &Dicom) -> Result<bool> {
+
+    let monochrome1 = get_photometric_interpretation(dicom)? == "MONOCHROME1";
+
+    let inverse_shape = get_str(dicom, Tag(0x2050, 0x0020))?.as_deref() == Some("INVERSE");
+
+    Ok(monochrome1 || inverse_shape)
+}
+
+fn get_palettes(dicom: &Dicom, signed: bool) -> Result<Palettes> {
+    (0..3).map(|channel| Lut::from_palette(dicom, channel, signed)).collect()
+}
+
+
+/*
+    Attribute values
+*/
+
+/// Integer value of a required attribute
+pub fn get_int<T: TryFrom<i64>>(item: &DicomItem, tag: Tag) -> Result<T> {
+    get_opt_int(item, tag)?.ok_or(DecodeError::MissingAttribute(tag))
+}
+
+/// Integer value of an optional attribute
+pub fn get_opt_int<T: TryFrom<i64>>(item: &DicomItem, tag: Tag) -> Result<Option<T>> {
+
+    let elem = match item.element(tag) {
+        Ok(elem) => elem,
+        Err(_) => return Ok(None)
+    };
+
+    let value: i64 = elem.to_int()
+        .map_err(|e| DecodeError::invalid_attribute(tag, e.to_string()))?;
+
+    T::try_from(value)
+        .map(Some)
+        .map_err(|_| DecodeError::invalid_attribute(tag, format!("out of range value {}", value)))
+}
+
+/// Integer values of an optional multi-valued attribute
+pub fn get_multi_int(item: &DicomItem, tag: Tag) -> Result<Option<Vec<i64>>> {
+    match item.element(tag) {
+        Ok(elem) => elem.to_multi_int()
+            .map(Some)
+            .map_err(|e| DecodeError::invalid_attribute(tag, e.to_string())),
+        Err(_) => Ok(None)
+    }
+}
+
+/// String value of an optional attribute, without padding
+pub fn get_str(item: &DicomItem, tag: Tag) -> Result<Option<String>> {
+    match item.element(tag) {
+        Ok(elem) => {
+            let value = elem.to_str().map_err(|e| DecodeError::invalid_attribute(tag, e.to_string()))?;
+            Ok(Some(value.trim_matches(|c: char| c.is_whitespace() || c == '\0').to_owned()))
+        },
+        Err(_) => Ok(None)
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt;
+use std::num::TryFromIntError;
+use dicom::object::Tag;
+
+
+/// Errors of the decoding pipeline. Malformed files and codestreams are reported
+/// with these errors instead of panicking.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The file cannot be read
+    Io(std::io::Error),
+    /// The file is not a DICOM file, or its data set cannot be parsed
+    InvalidFile(String),
+    UnsupportedTransferSyntax(String),
+    MissingAttribute(Tag),
+    InvalidAttribute { tag: Tag, message: String },
+    /// Valid data using a feature we do not support, such as a bit depth
+    Unsupported(String),
+    /// Compressed data, RLE segments or encapsulated fragments that cannot be decoded
+    CorruptCodestream(String),
+    /// Pixel data or decoded images whose size does not match the image attributes
+    InconsistentDimensions(String),
+    /// Error while decoding one of the frames, from 0
+    Frame { index: usize, source: Box<DecodeError> }
+}
+
+pub type Result<T, E = DecodeError> = std::result::Result<T, E>;
+
+impl DecodeError {
+
+    pub fn invalid_attribute(tag: Tag, message: impl Into<String>) -> Self {
+        DecodeError::InvalidAttribute { tag, message: message.into() }
+    }
+
+    pub fn unsupported(message: impl Into<String>) -> Self {
+        DecodeError::Unsupported(message.into())
+    }
+
+    pub fn corrupt(message: impl Into<String>) -> Self {
+        DecodeError::CorruptCodestream(message.into())
+    }
+
+    pub fn dimensions(message: impl Into<String>) -> Self {
+        DecodeError::InconsistentDimensions(message.into())
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Io(error) => write!(f, "Cannot read file: {}", error),
+            DecodeError::InvalidFile(message) => write!(f, "Invalid DICOM file: {}", message),
+            DecodeError::UnsupportedTransferSyntax(uid) => write!(f, "Unhandled transfer syntax: {}", uid),
+            DecodeError::MissingAttribute(tag) => write!(f, "Missing attribute {}", tag),
+            DecodeError::InvalidAttribute { tag, message } => write!(f, "Invalid attribute {}: {}", tag, message),
+            DecodeError::Unsupported(message)
+            | DecodeError::CorruptCodestream(message)
+            | DecodeError::InconsistentDimensions(message) => write!(f, "{}", message),
+            DecodeError::Frame { index, source } => write!(f, "Frame {}: {}", index + 1, source)
+        }
+    }
+}
+
+// The messages of the underlying errors are part of the Display output
+impl std::error::Error for DecodeError {}
+
+impl From<std::io::Error> for DecodeError {
+    fn from(error: std::io::Error) -> Self {
+        DecodeError::Io(error)
+    }
+}
+
+/// Sizes that do not fit in the integer type they are converted to
+impl From<TryFromIntError> for DecodeError {
+    fn from(error: TryFromIntError) -> Self {
+        DecodeError::Unsupported(format!("Value out of range: {}", error))
+    }
+}
+
+/// Message of an error followed by the messages of its causes
+pub fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(&format!(": {}", cause));
+        source = cause.source();
+    }
+    message
+}
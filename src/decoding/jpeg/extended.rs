@@ -0,0 +1,448 @@
+use std::f32::consts::PI;
+use crate::decoding::error::{DecodeError, Result};
+
+use super::{
+    DecodedImage, FrameHeader, ScanHeader, HuffmanTables, Reader, BitReader,
+    parse_frame_header, parse_scan_header, parse_huffman_tables, parse_restart_interval,
+    SOI, EOI, SOS, DHT, DRI, RST0, RST7
+};
+
+const SOF0: u8 = 0xC0;
+const SOF1: u8 = 0xC1;
+const DQT: u8 = 0xDB;
+const APP14: u8 = 0xEE;
+
+const ZIGZAG: [usize; 64] = [
+     0,  1,  8, 16,  9,  2,  3, 10,
+    17, 24, 32, 25, 18, 11,  4,  5,
+    12, 19, 26, 33, 40, 48, 41, 34,
+    27, 20, 13,  6,  7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36,
+    29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46,
+    53, 60, 61, 54, 47, 55, 62, 63
+];
+
+/// Entries in zigzag order, `wide` for 16-bit entries
+struct QuantizationTable {
+    entries: [u16; 64],
+    wide: bool
+}
+
+type QuantizationTables = [Option<QuantizationTable>; 4];
+
+/// Component samples, padded to a whole number of MCUs
+struct Plane {
+    w: usize,
+    h: usize,
+    samples: Vec<u16>
+}
+
+
+/// Decodes a sequential DCT-based JPEG codestream with Huffman coding
+/// (Baseline and Extended, Processes 1, 2 and 4), in 8 or 12-bit precision.
+pub fn decode_extended(data: &[u8], size: (u32, u32)) -> Result<DecodedImage> {
+
+    let mut reader = Reader::new(data);
+
+    if reader.next_marker() != Some(SOI) {
+        return Err(DecodeError::corrupt("JPEG: missing SOI marker"))
+    }
+
+    let mut dc_tables: HuffmanTables = Default::default();
+    let mut ac_tables: HuffmanTables = Default::default();
+    let mut quantization_tables: QuantizationTables = Default::default();
+    let mut frame: Option<FrameHeader> = None;
+    let mut planes: Vec<Plane> = Vec::new();
+    let mut restart_interval = 0;
+    let mut adobe_transform: Option<u8> = None;
+
+    while let Some(marker) = reader.next_marker() {
+
+        match marker {
+
+            SOF0 | SOF1 => {
+                let header = parse_frame_header(reader.segment()?, size)?;
+                if header.precision != 8 && header.precision != 12 {
+                    return Err(DecodeError::unsupported(format!(
+                        "JPEG: unsupported precision: {} bits", header.precision
+                    )))
+                }
+                planes = allocate_planes(&header)?;
+                frame = Some(header);
+            },
+
+            0xC0..=0xCF if marker != DHT && marker != 0xC8 && marker != 0xCC => return Err(DecodeError::unsupported(format!(
+                "JPEG: unsupported frame type SOF{}", marker - 0xC0
+            ))),
+
+            DHT => parse_huffman_tables(reader.segment()?, &mut dc_tables, &mut ac_tables)?,
+
+            DQT => parse_quantization_tables(reader.segment()?, &mut quantization_tables)?,
+
+            DRI => restart_interval = parse_restart_interval(reader.segment()?)?,
+
+            APP14 => {
+                let segment = reader.segment()?;
+                if segment.len() >= 12 && segment.starts_with(b"Adobe") {
+                    adobe_transform = Some(segment[11]);
+                }
+            },
+
+            SOS => {
+                let frame = frame.as_ref()
+                    .ok_or_else(|| DecodeError::corrupt("JPEG: scan before frame header"))?;
+                let scan = parse_scan_header(reader.segment()?, frame)?;
+                let scan_data = &data[reader.pos..];
+                let tables = ScanTables {
+                    dc: &dc_tables,
+                    ac: &ac_tables,
+                    quantization: &quantization_tables
+                };
+                reader.pos += decode_scan(scan_data, frame, &scan, &tables, restart_interval, &mut planes)?;
+            },
+
+            EOI => break,
+
+            RST0..=RST7 => (),
+
+            _ => { reader.segment()?; }
+        }
+    }
+
+    let frame = frame.ok_or_else(|| DecodeError::corrupt("JPEG: missing frame header"))?;
+
+    /*
+        Upsampling and color conversion
+    */
+
+    let w = usize::from(frame.w);
+    let h = usize::from(frame.h);
+    let h_max = frame.components.iter().map(|c| c.h).max().unwrap_or(1);
+    let v_max = frame.components.iter().map(|c| c.v).max().unwrap_or(1);
+
+    let nb_channels = planes.len();
+    let mut samples = Vec::with_capacity(w * h * nb_channels);
+
+    for y in 0..h {
+        for x in 0..w {
+            for (component, plane) in frame.components.iter().zip(planes.iter()) {
+                let cx = x * usize::from(component.h) / usize::from(h_max);
+                let cy = y * usize::from(component.v) / usize::from(v_max);
+                samples.push(plane.samples[cy * plane.w + cx]);
+            }
+        }
+    }
+
+    let is_rgb = frame.components.iter().map(|c| c.id).eq(b"RGB".iter().copied());
+    let is_ycbcr = match adobe_transform {
+        Some(transform) => transform != 0,
+        None => !is_rgb
+    };
+
+    if nb_channels == 3 && is_ycbcr {
+        ycbcr_to_rgb(&mut samples, frame.precision);
+    }
+
+    Ok(DecodedImage {
+        w: frame.w.into(),
+        h: frame.h.into(),
+        channels: nb_channels as u32,
+        samples
+    })
+}
+
+fn allocate_planes(frame: &FrameHeader) -> Result<Vec<Plane>> {
+
+    let h_max = usize::from(frame.components.iter().map(|c| c.h).max().unwrap_or(1));
+    let v_max = usize::from(frame.components.iter().map(|c| c.v).max().unwrap_or(1));
+
+    if !frame.components.iter().all(|c| (1..=4).contains(&c.h) && (1..=4).contains(&c.v)) {
+        return Err(DecodeError::corrupt("JPEG: invalid sampling factors"))
+    }
+
+    let mcus_x = usize::from(frame.w).div_ceil(8 * h_max);
+    let mcus_y = usize::from(frame.h).div_ceil(8 * v_max);
+
+    let planes = frame.components.iter()
+        .map(|c| {
+            let w = mcus_x * usize::from(c.h) * 8;
+            let h = mcus_y * usize::from(c.v) * 8;
+            Plane { w, h, samples: vec![0; w * h] }
+        })
+        .collect();
+
+    Ok(planes)
+}
+
+fn parse_quantization_tables(segment: &[u8], tables: &mut QuantizationTables) -> Result<()> {
+
+    let mut i = 0;
+
+    while i < segment.len() {
+
+        let precision = segment[i] >> 4;
+        let index: usize = (segment[i] & 0x0F).into();
+        if index >= 4 {
+            return Err(DecodeError::corrupt(format!("JPEG: invalid quantization table index {}", index)))
+        }
+
+        let entry_size = match precision {
+            0 => 1,
+            1 => 2,
+            _ => return Err(DecodeError::corrupt(format!(
+                "JPEG: invalid quantization table precision {}", precision
+            )))
+        };
+
+        if i + 1 + 64 * entry_size > segment.len() {
+            return Err(DecodeError::corrupt("JPEG: truncated quantization table"))
+        }
+
+        let mut entries = [0u16; 64];
+        for (k, entry) in entries.iter_mut().enumerate() {
+            let o = i + 1 + k * entry_size;
+            *entry = match entry_size {
+                1 => segment[o].into(),
+                _ => u16::from_be_bytes([segment[o], segment[o + 1]])
+            };
+        }
+
+        tables[index] = Some(QuantizationTable { entries, wide: entry_size == 2 });
+        i += 1 + 64 * entry_size;
+    }
+
+    Ok(())
+}
+
+
+/*
+    Scan decoding
+*/
+
+struct ScanTables<'a> {
+    dc: &'a HuffmanTables,
+    ac: &'a HuffmanTables,
+    quantization: &'a QuantizationTables
+}
+
+struct ComponentDecoder<'a> {
+    plane_index: usize,
+    h: usize,
+    v: usize,
+    dc_table: &'a super::HuffmanTable,
+    ac_table: &'a super::HuffmanTable,
+    quantization: &'a [u16; 64],
+    dc_prediction: i32
+}
+
+fn decode_scan(
+    data: &[u8],
+    frame: &FrameHeader,
+    scan: &ScanHeader,
+    tables: &ScanTables,
+    restart_interval: usize,
+    planes: &mut [Plane]
+) -> Result<usize> {
+
+    let mut components = scan.components.iter()
+        .map(|sc| {
+            let fc = &frame.components[sc.index];
+            let quantization = tables.quantization.get(usize::from(fc.tq))
+                .and_then(|t| t.as_ref())
+                .ok_or_else(|| DecodeError::corrupt(format!("JPEG: missing quantization table {}", fc.tq)))?;
+            if quantization.wide && frame.precision != 12 {
+                return Err(DecodeError::corrupt(format!(
+                    "JPEG: 16-bit quantization table {} for {}-bit samples", fc.tq, frame.precision
+                )))
+            }
+            let dc_table = tables.dc.get(sc.dc_table)
+                .and_then(|t| t.as_ref())
+                .ok_or_else(|| DecodeError::corrupt(format!("JPEG: missing DC Huffman table {}", sc.dc_table)))?;
+            let ac_table = tables.ac.get(sc.ac_table)
+                .and_then(|t| t.as_ref())
+                .ok_or_else(|| DecodeError::corrupt(format!("JPEG: missing AC Huffman table {}", sc.ac_table)))?;
+            Ok(ComponentDecoder {
+                plane_index: sc.index,
+                h: fc.h.into(),
+                v: fc.v.into(),
+                dc_table,
+                ac_table,
+                quantization: &quantization.entries,
+                dc_prediction: 0
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let h_max = usize::from(frame.components.iter().map(|c| c.h).max().unwrap_or(1));
+    let v_max = usize::from(frame.components.iter().map(|c| c.v).max().unwrap_or(1));
+
+    // A non-interleaved scan covers the blocks of a single component, one block per MCU
+    let (mcus_x, mcus_y) = match components.as_mut_slice() {
+        [c] => {
+            let comp_w = (usize::from(frame.w) * c.h).div_ceil(h_max);
+            let comp_h = (usize::from(frame.h) * c.v).div_ceil(v_max);
+            c.h = 1;
+            c.v = 1;
+            (comp_w.div_ceil(8), comp_h.div_ceil(8))
+        },
+        _ => (
+            usize::from(frame.w).div_ceil(8 * h_max),
+            usize::from(frame.h).div_ceil(8 * v_max)
+        )
+    };
+
+    let idct = IDCT::new();
+    let mut bits = BitReader::new(data);
+    let mut mcus_left = restart_interval;
+    let mut coefficients = [0i32; 64];
+
+    for mcu_y in 0..mcus_y {
+        for mcu_x in 0..mcus_x {
+
+            if restart_interval > 0 {
+                if mcus_left == 0 {
+                    bits.restart()?;
+                    mcus_left = restart_interval;
+                    for c in components.iter_mut() {
+                        c.dc_prediction = 0;
+                    }
+                }
+                mcus_left -= 1;
+            }
+
+            for c in components.iter_mut() {
+                for block_y in 0..c.v {
+                    for block_x in 0..c.h {
+                        decode_block(&mut bits, c, frame.precision, &mut coefficients)?;
+                        let plane = &mut planes[c.plane_index];
+                        let x0 = (mcu_x * c.h + block_x) * 8;
+                        let y0 = (mcu_y * c.v + block_y) * 8;
+                        idct.apply(&coefficients, frame.precision, plane, x0, y0);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(bits.position())
+}
+
+/// DC differences of P-bit samples have at most P + 3 bits, AC coefficients P + 2 bits
+fn decode_block(bits: &mut BitReader, c: &mut ComponentDecoder, precision: u8, coefficients: &mut [i32; 64]) -> Result<()> {
+
+    *coefficients = [0; 64];
+
+    let size = bits.decode(c.dc_table)?;
+    if size > precision + 3 {
+        return Err(DecodeError::corrupt(format!("JPEG: invalid DC difference size: {}", size)))
+    }
+    let overflow = || DecodeError::corrupt("JPEG: DC coefficient overflow");
+    c.dc_prediction = c.dc_prediction.checked_add(bits.receive_extend(size)).ok_or_else(overflow)?;
+    coefficients[0] = c.dc_prediction.checked_mul(i32::from(c.quantization[0])).ok_or_else(overflow)?;
+
+    let mut k = 1;
+    while k < 64 {
+
+        let rs = bits.decode(c.ac_table)?;
+        let run = usize::from(rs >> 4);
+        let size = rs & 0x0F;
+
+        if size == 0 {
+            match run {
+                15 => { k += 16; continue },
+                _ => break
+            }
+        }
+
+        if size > precision + 2 {
+            return Err(DecodeError::corrupt(format!("JPEG: invalid AC coefficient size: {}", size)))
+        }
+
+        k += run;
+        if k >= 64 {
+            return Err(DecodeError::corrupt("JPEG: corrupt AC coefficients"))
+        }
+        coefficients[ZIGZAG[k]] = bits.receive_extend(size) * i32::from(c.quantization[k]);
+        k += 1;
+    }
+
+    Ok(())
+}
+
+
+/*
+    Inverse DCT
+*/
+
+#[allow(clippy::upper_case_acronyms)]
+struct IDCT {
+    cosines: [[f32; 8]; 8]
+}
+
+impl IDCT {
+
+    fn new() -> Self {
+
+        let mut cosines = [[0f32; 8]; 8];
+
+        for (x, row) in cosines.iter_mut().enumerate() {
+            for (u, v) in row.iter_mut().enumerate() {
+                let c = if u == 0 { 1.0 / 2f32.sqrt() } else { 1.0 };
+                *v = c / 2.0 * ((2 * x + 1) as f32 * u as f32 * PI / 16.0).cos();
+            }
+        }
+
+        IDCT { cosines }
+    }
+
+    fn apply(&self, coefficients: &[i32; 64], precision: u8, plane: &mut Plane, x0: usize, y0: usize) {
+
+        let mut tmp = [0f32; 64];
+
+        // Rows
+        for v in 0..8 {
+            for x in 0..8 {
+                tmp[v * 8 + x] = (0..8)
+                    .map(|u| self.cosines[x][u] * coefficients[v * 8 + u] as f32)
+                    .sum();
+            }
+        }
+
+        // Columns
+        let level_shift = (1 << (precision - 1)) as f32;
+        let max = ((1 << precision) - 1) as f32;
+
+        for y in 0..8 {
+            if y0 + y >= plane.h {
+                break;
+            }
+            for x in 0..8 {
+                let value: f32 = (0..8)
+                    .map(|v| self.cosines[y][v] * tmp[v * 8 + x])
+                    .sum();
+                let value = (value + level_shift).round().max(0.0).min(max);
+                plane.samples[(y0 + y) * plane.w + x0 + x] = value as u16;
+            }
+        }
+    }
+}
+
+
+fn ycbcr_to_rgb(samples: &mut [u16], precision: u8) {
+
+    let center = (1 << (precision - 1)) as f32;
+    let max = ((1 << precision) - 1) as f32;
+    let clamp = |v: f32| v.round().max(0.0).min(max) as u16;
+
+    for pixel in samples.chunks_exact_mut(3) {
+
+        let y = f32::from(pixel[0]);
+        let cb = f32::from(pixel[1]) - center;
+        let cr = f32::from(pixel[2]) - center;
+
+        pixel[0] = clamp(y + 1.402 * cr);
+        pixel[1] = clamp(y - 0.344_136 * cb - 0.714_136 * cr);
+        pixel[2] = clamp(y + 1.772 * cb);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::decoding::error::{DecodeError, Result};
+
+use super::{
+    DecodedImage, FrameHeader, ScanHeader, HuffmanTables, Reader, BitReader,
+    parse_frame_header, parse_scan_header, parse_huffman_tables, parse_restart_interval,
+    SOI, EOI, SOS, DHT, DRI, RST0, RST7
+};
+
+const SOF3: u8 = 0xC3;
+
+
+/// Decodes a JPEG Lossless (Process 14) codestream, for any of the 7 predictors.
+pub fn decode_lossless(data: &[u8], size: (u32, u32)) -> Result<DecodedImage> {
+
+    let mut reader = Reader::new(data);
+
+    if reader.next_marker() != Some(SOI) {
+        return Err(DecodeError::corrupt("JPEG Lossless: missing SOI marker"))
+    }
+
+    let mut dc_tables: HuffmanTables = Default::default();
+    let mut ac_tables: HuffmanTables = Default::default();
+    let mut frame: Option<FrameHeader> = None;
+    let mut planes: Vec<Vec<u16>> = Vec::new();
+    let mut restart_interval = 0;
+
+    while let Some(marker) = reader.next_marker() {
+
+        match marker {
+
+            SOF3 => {
+                let header = parse_frame_header(reader.segment()?, size)?;
+                let nb_samples = usize::from(header.w) * usize::from(header.h);
+                planes = vec![vec![0; nb_samples]; header.components.len()];
+                frame = Some(header);
+            },
+
+            0xC0..=0xCF if marker != DHT && marker != 0xC8 && marker != 0xCC => return Err(DecodeError::unsupported(format!(
+                "JPEG Lossless: unsupported frame type SOF{}", marker - 0xC0
+            ))),
+
+            DHT => parse_huffman_tables(reader.segment()?, &mut dc_tables, &mut ac_tables)?,
+
+            DRI => restart_interval = parse_restart_interval(reader.segment()?)?,
+
+            SOS => {
+                let frame = frame.as_ref()
+                    .ok_or_else(|| DecodeError::corrupt("JPEG Lossless: scan before frame header"))?;
+                let scan = parse_scan_header(reader.segment()?, frame)?;
+                let scan_data = &data[reader.pos..];
+                reader.pos += decode_scan(scan_data, frame, &scan, &dc_tables, restart_interval, &mut planes)?;
+            },
+
+            EOI => break,
+
+            RST0..=RST7 => (),
+
+            _ => { reader.segment()?; }
+        }
+    }
+
+    let frame = frame.ok_or_else(|| DecodeError::corrupt("JPEG Lossless: missing frame header"))?;
+
+    /*
+        Interleaving components
+    */
+
+    let nb_samples = usize::from(frame.w) * usize::from(frame.h);
+    let mut samples = Vec::with_capacity(nb_samples * planes.len());
+    for i in 0..nb_samples {
+        for plane in planes.iter() {
+            samples.push(plane[i]);
+        }
+    }
+
+    Ok(DecodedImage {
+        w: frame.w.into(),
+        h: frame.h.into(),
+        channels: planes.len() as u32,
+        samples
+    })
+}
+
+fn decode_scan(
+    data: &[u8],
+    frame: &FrameHeader,
+    scan: &ScanHeader,
+    dc_tables: &HuffmanTables,
+    restart_interval: usize,
+    planes: &mut [Vec<u16>]
+) -> Result<usize> {
+
+    let w = usize::from(frame.w);
+    let h = usize::from(frame.h);
+    let precision = frame.precision;
+    let predictor = scan.ss;
+    let point_transform = scan.al;
+
+    if !(2..=16).contains(&precision) {
+        return Err(DecodeError::unsupported(format!(
+            "JPEG Lossless: unsupported precision: {} bits", precision
+        )))
+    }
+    if !(1..=7).contains(&predictor) {
+        return Err(DecodeError::unsupported(format!("JPEG Lossless: unsupported predictor: {}", predictor)))
+    }
+    if point_transform >= precision {
+        return Err(DecodeError::corrupt(format!(
+            "JPEG Lossless: invalid point transform: {}", point_transform
+        )))
+    }
+    if !(scan.components.len() == 1 || scan.components.iter()
+            .all(|c| frame.components[c.index].h == 1 && frame.components[c.index].v == 1)) {
+        return Err(DecodeError::unsupported("JPEG Lossless: subsampled components are not supported"))
+    }
+
+    let tables = scan.components.iter()
+        .map(|c| dc_tables.get(c.dc_table)
+            .and_then(|t| t.as_ref())
+            .ok_or_else(|| DecodeError::corrupt(format!("JPEG Lossless: missing Huffman table {}", c.dc_table))))
+        .collect::<Result<Vec<_>>>()?;
+
+    let initial_prediction: i32 = 1 << (precision - point_transform - 1);
+
+    let mut bits = BitReader::new(data);
+    let mut mcus_left = restart_interval;
+    let mut restart_pos = (0, 0);
+
+    for y in 0..h {
+        for x in 0..w {
+
+            if restart_interval > 0 {
+                if mcus_left == 0 {
+                    bits.restart()?;
+                    mcus_left = restart_interval;
+                    restart_pos = (y, x);
+                }
+                mcus_left -= 1;
+            }
+
+            for (component, table) in scan.components.iter().zip(tables.iter()) {
+
+                let plane = &mut planes[component.index];
+
+                /*
+                    Difference
+                */
+
+                let diff = match bits.decode(table)? {
+                    16 => 32768,
+                    size if size < 16 => bits.receive_extend(size),
+                    size => return Err(DecodeError::corrupt(format!(
+                        "JPEG Lossless: invalid difference size: {}", size
+                    )))
+                };
+
+                /*
+                    Prediction
+                */
+
+                let i = y * w + x;
+                let ra = || i32::from(plane[i - 1]);
+                let rb = || i32::from(plane[i - w]);
+                let rc = || i32::from(plane[i - w - 1]);
+
+                let prediction = if (y, x) == restart_pos {
+                    initial_prediction
+                } else if y == restart_pos.0 {
+                    ra()
+                } else if x == 0 {
+                    rb()
+                } else {
+                    match predictor {
+                        1 => ra(),
+                        2 => rb(),
+                        3 => rc(),
+                        4 => ra() + rb() - rc(),
+                        5 => ra() + ((rb() - rc()) >> 1),
+                        6 => rb() + ((ra() - rc()) >> 1),
+                        _ => (ra() + rb()) / 2
+                    }
+                };
+
+                plane[i] = ((prediction + diff) & 0xFFFF) as u16;
+            }
+        }
+    }
+
+    /*
+        Point transform
+    */
+
+    for component in scan.components.iter() {
+        for v in planes[component.index].iter_mut() {
+            *v <<= point_transform;
+        }
+    }
+
+    Ok(bits.position())
+}
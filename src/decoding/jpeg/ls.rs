@@ -0,0 +1,845 @@
+use crate::decoding::error::{DecodeError, Result};
+
+use super::{
+    DecodedImage, FrameHeader, Reader, parse_frame_header, parse_restart_interval,
+    SOI, EOI, SOS, DHT, DRI, RST0, RST7
+};
+
+const SOF55: u8 = 0xF7;
+const LSE: u8 = 0xF8;
+
+/// Run length order for each run index (J table, A.7.1.2)
+const J: [u32; 32] = [
+    0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3,
+    4, 4, 5, 5, 6, 6, 7, 7, 8, 9, 10, 11, 12, 13, 14, 15
+];
+
+const NB_CONTEXTS: usize = 365;
+
+const BASIC_T1: i32 = 3;
+const BASIC_T2: i32 = 7;
+const BASIC_T3: i32 = 21;
+
+
+/// Decodes a JPEG-LS codestream (ITU-T T.87), lossless or near-lossless,
+/// in any of the 3 interleave modes.
+pub fn decode_ls(data: &[u8], size: (u32, u32)) -> Result<DecodedImage> {
+
+    let mut reader = Reader::new(data);
+
+    if reader.next_marker() != Some(SOI) {
+        return Err(DecodeError::corrupt("JPEG-LS: missing SOI marker"))
+    }
+
+    let mut frame: Option<FrameHeader> = None;
+    let mut planes: Vec<Vec<u16>> = Vec::new();
+    let mut preset = PresetParameters::default();
+    let mut restart_interval = 0;
+
+    while let Some(marker) = reader.next_marker() {
+
+        match marker {
+
+            SOF55 => {
+                let header = parse_frame_header(reader.segment()?, size)?;
+                if !(2..=16).contains(&header.precision) {
+                    return Err(DecodeError::unsupported(format!(
+                        "JPEG-LS: unsupported precision: {} bits", header.precision
+                    )))
+                }
+                if !header.components.iter().all(|c| c.h == 1 && c.v == 1) {
+                    return Err(DecodeError::unsupported("JPEG-LS: subsampled components are not supported"))
+                }
+                let nb_samples = usize::from(header.w) * usize::from(header.h);
+                planes = vec![vec![0; nb_samples]; header.components.len()];
+                frame = Some(header);
+            },
+
+            0xC0..=0xCF if marker != DHT && marker != 0xC8 && marker != 0xCC => return Err(DecodeError::corrupt(format!(
+                "JPEG-LS: unexpected frame type SOF{}", marker - 0xC0
+            ))),
+
+            LSE => preset = parse_preset_parameters(reader.segment()?)?,
+
+            DRI => restart_interval = parse_restart_interval(reader.segment()?)?,
+
+            SOS => {
+                let frame = frame.as_ref()
+                    .ok_or_else(|| DecodeError::corrupt("JPEG-LS: scan before frame header"))?;
+                let scan = parse_scan_header(reader.segment()?, frame)?;
+                let scan_data = &data[reader.pos..];
+                reader.pos += decode_scan(scan_data, frame, &scan, &preset, restart_interval, &mut planes)?;
+            },
+
+            EOI => break,
+
+            RST0..=RST7 => (),
+
+            _ => { reader.segment()?; }
+        }
+    }
+
+    let frame = frame.ok_or_else(|| DecodeError::corrupt("JPEG-LS: missing frame header"))?;
+
+    /*
+        Interleaving components
+    */
+
+    let nb_samples = usize::from(frame.w) * usize::from(frame.h);
+    let mut samples = Vec::with_capacity(nb_samples * planes.len());
+    for i in 0..nb_samples {
+        for plane in planes.iter() {
+            samples.push(plane[i]);
+        }
+    }
+
+    Ok(DecodedImage {
+        w: frame.w.into(),
+        h: frame.h.into(),
+        channels: planes.len() as u32,
+        samples
+    })
+}
+
+
+/*
+    Headers
+*/
+
+/// Coding parameters from an LSE marker segment, zero meaning default
+#[derive(Default, Clone, Copy)]
+struct PresetParameters {
+    maxval: i32,
+    t1: i32,
+    t2: i32,
+    t3: i32,
+    reset: i32
+}
+
+struct ScanHeader {
+    components: Vec<usize>,
+    near: i32,
+    interleave_mode: u8,
+    point_transform: u8
+}
+
+fn parse_preset_parameters(segment: &[u8]) -> Result<PresetParameters> {
+
+    match segment.first() {
+        Some(1) => (),
+        Some(id) => return Err(DecodeError::unsupported(format!(
+            "JPEG-LS: unsupported LSE marker segment type {}", id
+        ))),
+        None => return Err(DecodeError::corrupt("JPEG-LS: truncated LSE marker segment"))
+    }
+
+    if segment.len() < 11 {
+        return Err(DecodeError::corrupt("JPEG-LS: truncated LSE marker segment"))
+    }
+
+    let value = |i: usize| i32::from(u16::from_be_bytes([segment[i], segment[i + 1]]));
+
+    Ok(PresetParameters {
+        maxval: value(1),
+        t1: value(3),
+        t2: value(5),
+        t3: value(7),
+        reset: value(9)
+    })
+}
+
+fn parse_scan_header(segment: &[u8], frame: &FrameHeader) -> Result<ScanHeader> {
+
+    if segment.is_empty() {
+        return Err(DecodeError::corrupt("JPEG-LS: truncated scan header"))
+    }
+
+    let nb_components: usize = segment[0].into();
+    if nb_components == 0 {
+        return Err(DecodeError::corrupt("JPEG-LS: scan has no components"))
+    }
+    if segment.len() < 4 + 2 * nb_components {
+        return Err(DecodeError::corrupt("JPEG-LS: truncated scan header"))
+    }
+
+    let components = segment[1..1 + 2 * nb_components]
+        .chunks_exact(2)
+        .map(|c| {
+            if c[1] != 0 {
+                return Err(DecodeError::unsupported("JPEG-LS: mapping tables are not supported"))
+            }
+            frame.components.iter()
+                .position(|fc| fc.id == c[0])
+                .ok_or_else(|| DecodeError::corrupt(format!("JPEG-LS: scan references unknown component {}", c[0])))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let params = &segment[1 + 2 * nb_components..];
+    let interleave_mode = params[1];
+
+    if interleave_mode > 2 {
+        return Err(DecodeError::corrupt(format!("JPEG-LS: invalid interleave mode {}", interleave_mode)))
+    }
+    if interleave_mode == 0 && components.len() > 1 {
+        return Err(DecodeError::corrupt(format!(
+            "JPEG-LS: non-interleaved scan with {} components", components.len()
+        )))
+    }
+
+    Ok(ScanHeader {
+        components,
+        near: params[0].into(),
+        interleave_mode,
+        point_transform: params[2] & 0x0F
+    })
+}
+
+
+/*
+    Coding parameters
+*/
+
+struct Parameters {
+    maxval: i32,
+    near: i32,
+    range: i32,
+    qbpp: u32,
+    limit: u32,
+    t1: i32,
+    t2: i32,
+    t3: i32,
+    reset: i32
+}
+
+impl Parameters {
+
+    fn new(precision: u8, near: i32, preset: &PresetParameters) -> Result<Self> {
+
+        if preset.maxval >= 1 << precision {
+            return Err(DecodeError::corrupt(format!("JPEG-LS: invalid MAXVAL {}", preset.maxval)))
+        }
+
+        let maxval = match preset.maxval {
+            0 => (1 << precision) - 1,
+            v => v
+        };
+
+        if near < 0 || near > (maxval / 2).min(255) {
+            return Err(DecodeError::corrupt(format!("JPEG-LS: invalid NEAR value {}", near)))
+        }
+
+        let range = (maxval + 2 * near) / (2 * near + 1) + 1;
+        let qbpp = ceil_log2(range);
+        let bpp = ceil_log2(maxval + 1).max(2);
+        let limit = 2 * (bpp + bpp.max(8));
+
+        /*
+            Default thresholds (C.2.4.1.1)
+        */
+
+        let clamp = |i: i32, j: i32| if i > maxval || i < j { j } else { i };
+
+        let (t1, t2, t3) = match maxval >= 128 {
+            true => {
+                let factor = (maxval.min(4095) + 128) / 256;
+                let t1 = clamp(factor * (BASIC_T1 - 2) + 2 + 3 * near, near + 1);
+                let t2 = clamp(factor * (BASIC_T2 - 3) + 3 + 5 * near, t1);
+                let t3 = clamp(factor * (BASIC_T3 - 4) + 4 + 7 * near, t2);
+                (t1, t2, t3)
+            },
+            false => {
+                let factor = 256 / (maxval + 1);
+                let t1 = clamp((BASIC_T1 / factor + 3 * near).max(2), near + 1);
+                let t2 = clamp((BASIC_T2 / factor + 5 * near).max(3), t1);
+                let t3 = clamp((BASIC_T3 / factor + 7 * near).max(4), t2);
+                (t1, t2, t3)
+            }
+        };
+
+        let or_default = |v: i32, default: i32| if v == 0 { default } else { v };
+
+        let t1 = or_default(preset.t1, t1);
+        let t2 = or_default(preset.t2, t2);
+        let t3 = or_default(preset.t3, t3);
+        let reset = or_default(preset.reset, 64);
+
+        /*
+            Bounds of the preset parameters (C.2.4.1.1)
+        */
+
+        if !(near < t1 && t1 <= t2 && t2 <= t3 && t3 <= maxval) {
+            return Err(DecodeError::corrupt(format!("JPEG-LS: invalid thresholds {}, {}, {}", t1, t2, t3)))
+        }
+        if !(3..=maxval.max(255)).contains(&reset) {
+            return Err(DecodeError::corrupt(format!("JPEG-LS: invalid RESET value {}", reset)))
+        }
+
+        Ok(Parameters { maxval, near, range, qbpp, limit, t1, t2, t3, reset })
+    }
+
+    fn quantize_gradient(&self, d: i32) -> i32 {
+        if d <= -self.t3 { -4 }
+        else if d <= -self.t2 { -3 }
+        else if d <= -self.t1 { -2 }
+        else if d < -self.near { -1 }
+        else if d <= self.near { 0 }
+        else if d < self.t1 { 1 }
+        else if d < self.t2 { 2 }
+        else if d < self.t3 { 3 }
+        else { 4 }
+    }
+
+    fn reconstruct(&self, prediction: i32, error: i32) -> i32 {
+
+        let step = 2 * self.near + 1;
+        let mut value = prediction + error * step;
+
+        if value < -self.near {
+            value += self.range * step;
+        } else if value > self.maxval + self.near {
+            value -= self.range * step;
+        }
+
+        value.max(0).min(self.maxval)
+    }
+}
+
+fn ceil_log2(v: i32) -> u32 {
+    let mut n = 0;
+    while (1i64 << n) < i64::from(v) {
+        n += 1;
+    }
+    n
+}
+
+
+/*
+    Context modeling
+*/
+
+#[derive(Clone, Copy)]
+struct Context {
+    a: i64,
+    b: i32,
+    c: i32,
+    n: i32
+}
+
+#[derive(Clone, Copy)]
+struct RunContext {
+    a: i64,
+    n: i32,
+    nn: i32
+}
+
+struct Decoder<'a, 'b> {
+    params: &'b Parameters,
+    bits: BitReader<'a>,
+    contexts: Vec<Context>,
+    run_contexts: [RunContext; 2],
+    run_index: usize
+}
+
+impl<'a, 'b> Decoder<'a, 'b> {
+
+    fn new(data: &'a [u8], params: &'b Parameters) -> Self {
+        let mut decoder = Decoder {
+            params,
+            bits: BitReader::new(data),
+            contexts: Vec::new(),
+            run_contexts: [RunContext { a: 0, n: 0, nn: 0 }; 2],
+            run_index: 0
+        };
+        decoder.reset();
+        decoder
+    }
+
+    fn reset(&mut self) {
+
+        let a = i64::from(((self.params.range + 32) / 64).max(2));
+
+        self.contexts = vec![Context { a, b: 0, c: 0, n: 1 }; NB_CONTEXTS];
+        self.run_contexts = [
+            RunContext { a, n: 1, nn: 0 },
+            RunContext { a, n: 1, nn: 0 }
+        ];
+        self.run_index = 0;
+    }
+
+    fn context_id(&self, ra: i32, rb: i32, rc: i32, rd: i32) -> i32 {
+        let p = self.params;
+        81 * p.quantize_gradient(rd - rb) + 9 * p.quantize_gradient(rb - rc) + p.quantize_gradient(rc - ra)
+    }
+
+    /// Limited-length Golomb code (A.5.3)
+    fn decode_value(&mut self, k: u32, limit: u32) -> Result<i32> {
+
+        let qbpp = self.params.qbpp;
+
+        let mut high_bits = 0;
+        while self.bits.read_bit() == 0 {
+            high_bits += 1;
+            if high_bits >= limit {
+                return Err(DecodeError::corrupt("JPEG-LS: corrupt Golomb code"))
+            }
+        }
+
+        let value = match high_bits >= limit - (qbpp + 1) {
+            true => u64::from(self.bits.read_bits(qbpp)) + 1,
+            false => (u64::from(high_bits) << k) + u64::from(self.bits.read_bits(k))
+        };
+
+        // Mapped errors are reduced modulo RANGE (A.4.5)
+        if value > 1 << qbpp {
+            return Err(DecodeError::corrupt("JPEG-LS: prediction error out of range"))
+        }
+
+        Ok(value as i32)
+    }
+
+    fn decode_regular(&mut self, qs: i32, ra: i32, rb: i32, rc: i32) -> Result<i32> {
+
+        let sign = if qs < 0 { -1 } else { 1 };
+        let q = (qs * sign) as usize;
+        let Context { a, b, c, n } = self.contexts[q];
+        let params = self.params;
+
+        /*
+            Prediction
+        */
+
+        let prediction = if rc >= ra.max(rb) {
+            ra.min(rb)
+        } else if rc <= ra.min(rb) {
+            ra.max(rb)
+        } else {
+            ra + rb - rc
+        };
+
+        let prediction = (prediction + sign * c).max(0).min(params.maxval);
+
+        /*
+            Prediction error
+        */
+
+        let mut k = 0;
+        while (i64::from(n) << k) < a {
+            k += 1;
+        }
+
+        let mapped_error = self.decode_value(k, params.limit)?;
+        let mut error = match mapped_error & 1 {
+            0 => mapped_error >> 1,
+            _ => -((mapped_error + 1) >> 1)
+        };
+
+        if params.near == 0 && k == 0 && 2 * b + n - 1 < 0 {
+            error = -error - 1;
+        }
+
+        /*
+            Context update (A.6)
+        */
+
+        let context = &mut self.contexts[q];
+        context.a += i64::from(error.abs());
+        context.b += error * (2 * params.near + 1);
+
+        if context.n == params.reset {
+            context.a >>= 1;
+            context.b >>= 1;
+            context.n >>= 1;
+        }
+        context.n += 1;
+
+        if context.b <= -context.n {
+            context.b += context.n;
+            if context.c > -128 {
+                context.c -= 1;
+            }
+            if context.b <= -context.n {
+                context.b = -context.n + 1;
+            }
+        } else if context.b > 0 {
+            context.b -= context.n;
+            if context.c < 127 {
+                context.c += 1;
+            }
+            if context.b > 0 {
+                context.b = 0;
+            }
+        }
+
+        Ok(params.reconstruct(prediction, sign * error))
+    }
+
+    /// Length of a run of samples equal to Ra, at most `nb_samples` (A.7.1)
+    fn decode_run_length(&mut self, nb_samples: usize) -> Result<usize> {
+
+        let mut length = 0;
+
+        while self.bits.read_bit() == 1 {
+
+            let segment = 1 << J[self.run_index];
+            let count = segment.min(nb_samples - length);
+            length += count;
+
+            if count == segment {
+                self.run_index = (self.run_index + 1).min(31);
+            }
+
+            if length == nb_samples {
+                return Ok(length);
+            }
+        }
+
+        length += self.bits.read_bits(J[self.run_index]) as usize;
+        if length > nb_samples {
+            return Err(DecodeError::corrupt("JPEG-LS: run exceeds line length"))
+        }
+
+        Ok(length)
+    }
+
+    /// Prediction error of a run interruption sample (A.7.2)
+    fn decode_run_interruption_error(&mut self, ritype: usize) -> Result<i32> {
+
+        let RunContext { a, n, nn, .. } = self.run_contexts[ritype];
+        let ri = ritype as i32;
+
+        let temp = match ritype {
+            0 => a,
+            _ => a + i64::from(n >> 1)
+        };
+
+        let mut k = 0;
+        while (i64::from(n) << k) < temp {
+            k += 1;
+        }
+
+        let limit = self.params.limit - J[self.run_index] - 1;
+        let mapped_error = self.decode_value(k, limit)?;
+
+        let t = mapped_error + ri;
+        let map = t & 1 == 1;
+        let abs_error = (t + t % 2) / 2;
+        let error = match (k != 0 || 2 * nn >= n) == map {
+            true => -abs_error,
+            false => abs_error
+        };
+
+        let context = &mut self.run_contexts[ritype];
+        if error < 0 {
+            context.nn += 1;
+        }
+        context.a += i64::from((mapped_error + 1 - ri) >> 1);
+        if context.n == self.params.reset {
+            context.a >>= 1;
+            context.n >>= 1;
+            context.nn >>= 1;
+        }
+        context.n += 1;
+
+        Ok(error)
+    }
+
+    fn end_run_interruption(&mut self) {
+        if self.run_index > 0 {
+            self.run_index -= 1;
+        }
+    }
+}
+
+
+/*
+    Scan decoding
+*/
+
+/// Lines of a component, with one extra sample on each side
+struct Lines {
+    previous: Vec<i32>,
+    current: Vec<i32>
+}
+
+impl Lines {
+
+    fn new(w: usize) -> Self {
+        Lines { previous: vec![0; w + 2], current: vec![0; w + 2] }
+    }
+
+    /// Sets up the edge samples before decoding a line (A.2.1)
+    fn start_line(&mut self, w: usize) {
+        self.current[0] = self.previous[1];
+        self.previous[w + 1] = self.previous[w];
+    }
+
+    fn neighbours(&self, x: usize) -> (i32, i32, i32, i32) {
+        (self.current[x], self.previous[x + 1], self.previous[x], self.previous[x + 2])
+    }
+
+    fn end_line(&mut self) {
+        std::mem::swap(&mut self.previous, &mut self.current);
+    }
+}
+
+fn decode_scan(
+    data: &[u8],
+    frame: &FrameHeader,
+    scan: &ScanHeader,
+    preset: &PresetParameters,
+    restart_interval: usize,
+    planes: &mut [Vec<u16>]
+) -> Result<usize> {
+
+    let w = usize::from(frame.w);
+    let h = usize::from(frame.h);
+
+    let params = Parameters::new(frame.precision, scan.near, preset)?;
+    let mut decoder = Decoder::new(data, &params);
+
+    let nb_components = scan.components.len();
+    let mut lines: Vec<Lines> = (0..nb_components).map(|_| Lines::new(w)).collect();
+    let mut run_indices = vec![0; nb_components];
+
+    for y in 0..h {
+
+        if restart_interval > 0 && y > 0 && y % restart_interval == 0 {
+            decoder.bits.restart()?;
+            decoder.reset();
+            run_indices.iter_mut().for_each(|i| *i = 0);
+            lines = (0..nb_components).map(|_| Lines::new(w)).collect();
+        }
+
+        match scan.interleave_mode {
+
+            2 => decode_sample_interleaved_line(&mut decoder, &mut lines, w)?,
+
+            _ => for (c, component_lines) in lines.iter_mut().enumerate() {
+                // Each component keeps its own run index in line interleaved mode
+                decoder.run_index = run_indices[c];
+                decode_line(&mut decoder, component_lines, w)?;
+                run_indices[c] = decoder.run_index;
+            }
+        }
+
+        for (component_lines, index) in lines.iter_mut().zip(scan.components.iter()) {
+            let plane = &mut planes[*index];
+            for x in 0..w {
+                plane[y * w + x] = (component_lines.current[x + 1] << scan.point_transform) as u16;
+            }
+            component_lines.end_line();
+        }
+    }
+
+    Ok(decoder.bits.position())
+}
+
+fn decode_line(decoder: &mut Decoder, lines: &mut Lines, w: usize) -> Result<()> {
+
+    lines.start_line(w);
+
+    let mut x = 0;
+
+    while x < w {
+
+        let (ra, rb, rc, rd) = lines.neighbours(x);
+        let qs = decoder.context_id(ra, rb, rc, rd);
+
+        if qs != 0 {
+            lines.current[x + 1] = decoder.decode_regular(qs, ra, rb, rc)?;
+            x += 1;
+            continue;
+        }
+
+        /*
+            Run mode
+        */
+
+        let length = decoder.decode_run_length(w - x)?;
+        for v in lines.current[x + 1..x + 1 + length].iter_mut() {
+            *v = ra;
+        }
+        x += length;
+
+        if x == w {
+            break;
+        }
+
+        let rb = lines.previous[x + 1];
+        lines.current[x + 1] = match (ra - rb).abs() <= decoder.params.near {
+            true => {
+                let error = decoder.decode_run_interruption_error(1)?;
+                decoder.params.reconstruct(ra, error)
+            },
+            false => {
+                let error = decoder.decode_run_interruption_error(0)?;
+                let sign = if rb < ra { -1 } else { 1 };
+                decoder.params.reconstruct(rb, sign * error)
+            }
+        };
+
+        decoder.end_run_interruption();
+        x += 1;
+    }
+
+    Ok(())
+}
+
+fn decode_sample_interleaved_line(decoder: &mut Decoder, lines: &mut [Lines], w: usize) -> Result<()> {
+
+    for component_lines in lines.iter_mut() {
+        component_lines.start_line(w);
+    }
+
+    let mut x = 0;
+    let mut qs = vec![0; lines.len()];
+
+    while x < w {
+
+        for (q, component_lines) in qs.iter_mut().zip(lines.iter()) {
+            let (ra, rb, rc, rd) = component_lines.neighbours(x);
+            *q = decoder.context_id(ra, rb, rc, rd);
+        }
+
+        if qs.iter().any(|q| *q != 0) {
+            for (q, component_lines) in qs.iter().zip(lines.iter_mut()) {
+                let (ra, rb, rc, _) = component_lines.neighbours(x);
+                component_lines.current[x + 1] = decoder.decode_regular(*q, ra, rb, rc)?;
+            }
+            x += 1;
+            continue;
+        }
+
+        /*
+            Run mode, on whole pixels
+        */
+
+        let length = decoder.decode_run_length(w - x)?;
+        for component_lines in lines.iter_mut() {
+            let ra = component_lines.current[x];
+            for v in component_lines.current[x + 1..x + 1 + length].iter_mut() {
+                *v = ra;
+            }
+        }
+        x += length;
+
+        if x == w {
+            break;
+        }
+
+        for component_lines in lines.iter_mut() {
+            let ra = component_lines.current[x];
+            let rb = component_lines.previous[x + 1];
+            let error = decoder.decode_run_interruption_error(0)?;
+            let sign = if rb < ra { -1 } else { 1 };
+            component_lines.current[x + 1] = decoder.params.reconstruct(rb, sign * error);
+        }
+
+        decoder.end_run_interruption();
+        x += 1;
+    }
+
+    Ok(())
+}
+
+
+/*
+    Entropy-coded data
+*/
+
+/// Bit reader for JPEG-LS, where a 0xFF byte is followed by a stuffed zero bit
+/// instead of a zero byte
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    acc: u64,
+    nb_bits: u32,
+    after_ff: bool,
+    marker: bool
+}
+
+impl<'a> BitReader<'a> {
+
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0, acc: 0, nb_bits: 0, after_ff: false, marker: false }
+    }
+
+    /// Appends one byte of data to the accumulator. Past the end of the
+    /// entropy-coded segment the data is padded with zeros.
+    fn fill(&mut self) {
+
+        let byte = match self.data.get(self.pos) {
+            Some(b) if !self.marker => *b,
+            _ => {
+                self.nb_bits += 8;
+                return;
+            }
+        };
+
+        if self.after_ff {
+            if byte & 0x80 != 0 {
+                self.marker = true;
+                self.nb_bits += 8;
+                return;
+            }
+            self.acc |= u64::from(byte) << (64 - 7 - self.nb_bits);
+            self.nb_bits += 7;
+            self.after_ff = false;
+        } else {
+            if byte == 0xFF && !matches!(self.data.get(self.pos + 1), Some(b) if b & 0x80 == 0) {
+                self.marker = true;
+                self.nb_bits += 8;
+                return;
+            }
+            self.acc |= u64::from(byte) << (64 - 8 - self.nb_bits);
+            self.nb_bits += 8;
+            self.after_ff = byte == 0xFF;
+        }
+
+        self.pos += 1;
+    }
+
+    fn read_bits(&mut self, n: u32) -> u32 {
+
+        if n == 0 {
+            return 0;
+        }
+
+        while self.nb_bits < n {
+            self.fill();
+        }
+
+        let bits = (self.acc >> (64 - n)) as u32;
+        self.acc <<= n;
+        self.nb_bits -= n;
+        bits
+    }
+
+    fn read_bit(&mut self) -> u32 {
+        self.read_bits(1)
+    }
+
+    /// Skips to the end of the current restart interval
+    fn restart(&mut self) -> Result<()> {
+
+        self.acc = 0;
+        self.nb_bits = 0;
+        self.after_ff = false;
+        self.marker = false;
+
+        while self.pos + 1 < self.data.len() {
+            if self.data[self.pos] == 0xFF && (RST0..=RST7).contains(&self.data[self.pos + 1]) {
+                self.pos += 2;
+                return Ok(());
+            }
+            self.pos += 1;
+        }
+
+        Err(DecodeError::corrupt("JPEG-LS: missing restart marker"))
+    }
+
+    /// Position in the data after the consumed bytes
+    fn position(&self) -> usize {
+        self.pos
+    }
+}
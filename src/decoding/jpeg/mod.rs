@@ -0,0 +1,462 @@
+use std::convert::TryInto;
+use super::error::{DecodeError, Result};
+
+mod lossless;
+mod extended;
+mod ls;
+pub use lossless::decode_lossless;
+pub use extended::decode_extended;
+pub use ls::decode_ls;
+
+
+/*
+    Markers
+*/
+
+const SOI: u8 = 0xD8;
+const EOI: u8 = 0xD9;
+const SOS: u8 = 0xDA;
+const DHT: u8 = 0xC4;
+const DRI: u8 = 0xDD;
+const RST0: u8 = 0xD0;
+const RST7: u8 = 0xD7;
+
+
+pub struct DecodedImage {
+    pub w: u32,
+    pub h: u32,
+    pub channels: u32,
+    pub samples: Vec<u16>
+}
+
+impl DecodedImage {
+
+    pub fn to_bytes(&self, channel_depth: u32) -> Result<Vec<u8>> {
+
+        let bytes = match channel_depth {
+            1 => self.samples.iter()
+                .map(|v| (*v).try_into())
+                .collect::<Result<Vec<u8>, _>>()
+                .map_err(|_| DecodeError::corrupt("JPEG: decoded samples do not fit in 8 bits"))?,
+            2 => self.samples.iter()
+                .flat_map(|v| v.to_le_bytes().to_vec())
+                .collect(),
+            _ => return Err(DecodeError::unsupported(format!(
+                "JPEG: unsupported bit depth: {} (1 and 2 supported)", channel_depth
+            )))
+        };
+
+        Ok(bytes)
+    }
+}
+
+
+/*
+    Headers
+*/
+
+struct FrameHeader {
+    precision: u8,
+    h: u16,
+    w: u16,
+    components: Vec<FrameComponent>
+}
+
+struct FrameComponent {
+    id: u8,
+    h: u8,
+    v: u8,
+    tq: u8
+}
+
+struct ScanHeader {
+    components: Vec<ScanComponent>,
+    ss: u8,
+    al: u8
+}
+
+struct ScanComponent {
+    index: usize,
+    dc_table: usize,
+    ac_table: usize
+}
+
+/// Frame headers must match the size of the DICOM image, `size`, so that corrupt
+/// headers cannot make the decoders allocate huge images
+fn parse_frame_header(segment: &[u8], size: (u32, u32)) -> Result<FrameHeader> {
+
+    if segment.len() < 6 {
+        return Err(DecodeError::corrupt("JPEG: truncated frame header"))
+    }
+
+    let precision = segment[0];
+    let h = u16::from_be_bytes([segment[1], segment[2]]);
+    let w = u16::from_be_bytes([segment[3], segment[4]]);
+    let nb_components: usize = segment[5].into();
+
+    if segment.len() < 6 + 3 * nb_components {
+        return Err(DecodeError::corrupt("JPEG: truncated frame header"))
+    }
+    if nb_components == 0 {
+        return Err(DecodeError::corrupt("JPEG: frame has no components"))
+    }
+    if (u32::from(w), u32::from(h)) != size {
+        return Err(DecodeError::dimensions(format!(
+            "JPEG: frame is {}x{}, expected {}x{}", w, h, size.0, size.1
+        )))
+    }
+
+    let components = segment[6..6 + 3 * nb_components]
+        .chunks_exact(3)
+        .map(|c| FrameComponent { id: c[0], h: c[1] >> 4, v: c[1] & 0x0F, tq: c[2] })
+        .collect();
+
+    Ok(FrameHeader { precision, h, w, components })
+}
+
+fn parse_scan_header(segment: &[u8], frame: &FrameHeader) -> Result<ScanHeader> {
+
+    if segment.is_empty() {
+        return Err(DecodeError::corrupt("JPEG: truncated scan header"))
+    }
+
+    let nb_components: usize = segment[0].into();
+    if segment.len() < 4 + 2 * nb_components {
+        return Err(DecodeError::corrupt("JPEG: truncated scan header"))
+    }
+
+    let components = segment[1..1 + 2 * nb_components]
+        .chunks_exact(2)
+        .map(|c| {
+            let index = frame.components.iter()
+                .position(|fc| fc.id == c[0])
+                .ok_or_else(|| DecodeError::corrupt(format!("JPEG: scan references unknown component {}", c[0])))?;
+            Ok(ScanComponent {
+                index,
+                dc_table: (c[1] >> 4).into(),
+                ac_table: (c[1] & 0x0F).into()
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    let params = &segment[1 + 2 * nb_components..];
+
+    Ok(ScanHeader { components, ss: params[0], al: params[2] & 0x0F })
+}
+
+/// Sample precision of the first frame header, if any
+pub fn sample_precision(data: &[u8]) -> Option<u8> {
+
+    let mut reader = Reader::new(data);
+
+    while let Some(marker) = reader.next_marker() {
+        match marker {
+            0xC0..=0xCF if marker != DHT && marker != 0xC8 && marker != 0xCC => {
+                return reader.segment().ok()?.first().copied();
+            },
+            SOI | RST0..=RST7 => (),
+            _ => { reader.segment().ok()?; }
+        }
+    }
+
+    None
+}
+
+fn parse_restart_interval(segment: &[u8]) -> Result<usize> {
+    if segment.len() < 2 {
+        return Err(DecodeError::corrupt("JPEG: truncated restart interval"))
+    }
+    Ok(u16::from_be_bytes([segment[0], segment[1]]).into())
+}
+
+
+/*
+    Marker segments
+*/
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    /// Finds the next marker, skipping fill bytes and any leftover entropy-coded data
+    fn next_marker(&mut self) -> Option<u8> {
+
+        while self.pos + 1 < self.data.len() {
+            let (b0, b1) = (self.data[self.pos], self.data[self.pos + 1]);
+            if b0 == 0xFF && b1 != 0x00 && b1 != 0xFF {
+                self.pos += 2;
+                return Some(b1);
+            }
+            self.pos += 1;
+        }
+
+        None
+    }
+
+    fn segment(&mut self) -> Result<&'a [u8]> {
+
+        if self.pos + 2 > self.data.len() {
+            return Err(DecodeError::corrupt("JPEG: truncated marker segment"))
+        }
+
+        let len: usize = u16::from_be_bytes([self.data[self.pos], self.data[self.pos + 1]]).into();
+        if len < 2 || self.pos + len > self.data.len() {
+            return Err(DecodeError::corrupt("JPEG: truncated marker segment"))
+        }
+
+        let segment = &self.data[self.pos + 2..self.pos + len];
+        self.pos += len;
+
+        Ok(segment)
+    }
+}
+
+
+/*
+    Huffman coding
+*/
+
+const LOOKUP_BITS: u32 = 8;
+
+pub struct HuffmanTable {
+    lookup: Vec<(u8, u8)>,
+    maxcode: [i32; 17],
+    valptr: [i32; 17],
+    mincode: [i32; 17],
+    values: Vec<u8>
+}
+
+impl HuffmanTable {
+
+    fn new(counts: &[u8], values: &[u8]) -> Result<Self> {
+
+        let mut maxcode = [-1i32; 17];
+        let mut valptr = [0i32; 17];
+        let mut mincode = [0i32; 17];
+        let mut lookup = vec![(0u8, 0u8); 1 << LOOKUP_BITS];
+
+        let mut code: i32 = 0;
+        let mut k: usize = 0;
+
+        for l in 1..17 {
+
+            let count: usize = counts[l - 1].into();
+            if code as usize + count > 1 << l {
+                return Err(DecodeError::corrupt("JPEG: invalid Huffman table"))
+            }
+
+            if count > 0 {
+                valptr[l] = k as i32;
+                mincode[l] = code;
+            }
+
+            for _ in 0..count {
+
+                if l as u32 <= LOOKUP_BITS {
+                    let shift = LOOKUP_BITS - l as u32;
+                    let first = (code as usize) << shift;
+                    for entry in lookup[first..first + (1 << shift)].iter_mut() {
+                        *entry = (values[k], l as u8);
+                    }
+                }
+
+                code += 1;
+                k += 1;
+            }
+
+            if count > 0 {
+                maxcode[l] = code - 1;
+            }
+
+            code <<= 1;
+        }
+
+        Ok(HuffmanTable { lookup, maxcode, valptr, mincode, values: values.to_vec() })
+    }
+}
+
+type HuffmanTables = [Option<HuffmanTable>; 4];
+
+fn parse_huffman_tables(
+    segment: &[u8],
+    dc_tables: &mut HuffmanTables,
+    ac_tables: &mut HuffmanTables
+) -> Result<()> {
+
+    let mut i = 0;
+
+    while i < segment.len() {
+
+        if i + 17 > segment.len() {
+            return Err(DecodeError::corrupt("JPEG: truncated Huffman table"))
+        }
+
+        let class = segment[i] >> 4;
+        let index: usize = (segment[i] & 0x0F).into();
+        if index >= 4 {
+            return Err(DecodeError::corrupt(format!("JPEG: invalid Huffman table index {}", index)))
+        }
+
+        let counts = &segment[i + 1..i + 17];
+        let nb_values: usize = counts.iter().map(|c| usize::from(*c)).sum();
+        if i + 17 + nb_values > segment.len() {
+            return Err(DecodeError::corrupt("JPEG: truncated Huffman table"))
+        }
+
+        let values = &segment[i + 17..i + 17 + nb_values];
+        let table = HuffmanTable::new(counts, values)?;
+
+        match class {
+            0 => dc_tables[index] = Some(table),
+            1 => ac_tables[index] = Some(table),
+            _ => return Err(DecodeError::corrupt(format!("JPEG: invalid Huffman table class {}", class)))
+        }
+
+        i += 17 + nb_values;
+    }
+
+    Ok(())
+}
+
+
+/*
+    Entropy-coded data
+*/
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    acc: u64,
+    nb_bits: u32,
+    marker: Option<u8>
+}
+
+impl<'a> BitReader<'a> {
+
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0, acc: 0, nb_bits: 0, marker: None }
+    }
+
+    /// Fills the accumulator, removing stuffed bytes. Past the end of the
+    /// entropy-coded segment the data is padded with zeros.
+    fn fill(&mut self) {
+
+        while self.nb_bits <= 56 {
+
+            let mut byte = 0;
+
+            if self.marker.is_none() && self.pos < self.data.len() {
+                byte = self.data[self.pos];
+                if byte == 0xFF {
+                    match self.data.get(self.pos + 1) {
+                        Some(0x00) => self.pos += 2,
+                        Some(m) => { self.marker = Some(*m); byte = 0 },
+                        None => { self.pos += 1; byte = 0 }
+                    }
+                } else {
+                    self.pos += 1;
+                }
+            }
+
+            self.acc |= u64::from(byte) << (56 - self.nb_bits);
+            self.nb_bits += 8;
+        }
+    }
+
+    fn peek(&mut self, n: u32) -> u32 {
+        if self.nb_bits < n {
+            self.fill();
+        }
+        (self.acc >> (64 - n)) as u32
+    }
+
+    fn consume(&mut self, n: u32) {
+        self.acc <<= n;
+        self.nb_bits -= n;
+    }
+
+    fn read_bits(&mut self, n: u32) -> u32 {
+        if n == 0 {
+            return 0;
+        }
+        let bits = self.peek(n);
+        self.consume(n);
+        bits
+    }
+
+    fn decode(&mut self, table: &HuffmanTable) -> Result<u8> {
+
+        let peeked = self.peek(16);
+
+        let (value, len) = table.lookup[(peeked >> (16 - LOOKUP_BITS)) as usize];
+        if len > 0 {
+            self.consume(len.into());
+            return Ok(value);
+        }
+
+        for l in LOOKUP_BITS + 1..17 {
+            let code = (peeked >> (16 - l)) as i32;
+            if code <= table.maxcode[l as usize] {
+                self.consume(l);
+                let k = table.valptr[l as usize] + code - table.mincode[l as usize];
+                return table.values.get(k as usize)
+                    .copied()
+                    .ok_or_else(|| DecodeError::corrupt("JPEG: corrupt Huffman code"));
+            }
+        }
+
+        Err(DecodeError::corrupt("JPEG: corrupt Huffman code"))
+    }
+
+    /// Reads a `size`-bit magnitude and sign-extends it (EXTEND procedure)
+    fn receive_extend(&mut self, size: u8) -> i32 {
+
+        if size == 0 {
+            return 0;
+        }
+
+        let size = u32::from(size);
+        let value = self.read_bits(size) as i32;
+
+        match value < 1 << (size - 1) {
+            true => value - (1 << size) + 1,
+            false => value
+        }
+    }
+
+    /// Skips to the end of the current restart interval
+    fn restart(&mut self) -> Result<()> {
+
+        self.acc = 0;
+        self.nb_bits = 0;
+
+        if self.marker.is_none() {
+            let mut reader = Reader::new(&self.data[self.pos..]);
+            let marker = reader.next_marker()
+                .ok_or_else(|| DecodeError::corrupt("JPEG: missing restart marker"))?;
+            self.pos += reader.pos - 2;
+            self.marker = Some(marker);
+        }
+
+        match self.marker {
+            Some(m) if (RST0..=RST7).contains(&m) => {
+                self.pos += 2;
+                self.marker = None;
+                Ok(())
+            },
+            _ => Err(DecodeError::corrupt("JPEG: missing restart marker"))
+        }
+    }
+
+    /// Position in the data after the consumed bytes
+    fn position(&self) -> usize {
+        self.pos
+    }
+}
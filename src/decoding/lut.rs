@@ -0,0 +1,210 @@
+use std::convert::TryInto;
+use dicom::object::Tag;
+use dicom::core::value::PrimitiveValue;
+
+use crate::utils::DicomItem;
+use super::error::{DecodeError, Result};
+use super::dicom_parsing::{get_multi_int, get_str};
+
+
+/// Lookup table of a LUT sequence item (Modality LUT, VOI LUT) or of a palette channel
+pub struct Lut {
+    pub first_mapped: i64,
+    pub bits: u32,
+    pub data: Vec<u16>
+}
+
+impl Lut {
+
+    /// Reads the LUT Descriptor (0028,3002) and LUT Data (0028,3006) of a sequence item.
+    /// The first mapped value is signed when the pixel data is.
+    pub fn from_item(item: &DicomItem, signed: bool) -> Result<Lut> {
+
+        let (nb_entries, first_mapped, bits) = get_descriptor(item, Tag(0x0028, 0x3002), signed)?;
+
+        let data = get_words(item, Tag(0x0028, 0x3006))?
+            .ok_or(DecodeError::MissingAttribute(Tag(0x0028, 0x3006)))?;
+
+        Lut::new(nb_entries, first_mapped, bits, data)
+            .map_err(|message| DecodeError::invalid_attribute(Tag(0x0028, 0x3006), message))
+    }
+
+    /// Reads one channel of a Palette Color LUT: the Palette Color Lookup Table Descriptor
+    /// (0028,1101-1103) and the Palette Color Lookup Table Data (0028,1201-1203) or
+    /// Segmented Palette Color Lookup Table Data (0028,1221-1223). Channels are 0 (red),
+    /// 1 (green) and 2 (blue).
+    pub fn from_palette(item: &DicomItem, channel: u16, signed: bool) -> Result<Lut> {
+
+        let (nb_entries, first_mapped, bits) = get_descriptor(item, Tag(0x0028, 0x1101 + channel), signed)?;
+
+        let (tag, data) = match get_words(item, Tag(0x0028, 0x1201 + channel))? {
+            Some(data) => (Tag(0x0028, 0x1201 + channel), data),
+            None => {
+                let tag = Tag(0x0028, 0x1221 + channel);
+                let segments = get_words(item, tag)?
+                    .ok_or(DecodeError::MissingAttribute(Tag(0x0028, 0x1201 + channel)))?;
+                let mut data = Vec::new();
+                expand_segments(&segments, &mut data)
+                    .map_err(|message| DecodeError::invalid_attribute(tag, message))?;
+                (tag, data)
+            }
+        };
+
+        Lut::new(nb_entries, first_mapped, bits, data)
+            .map_err(|message| DecodeError::invalid_attribute(tag, message))
+    }
+
+    fn new(nb_entries: usize, first_mapped: i64, bits: u32, data: Vec<u16>) -> Result<Lut, String> {
+
+        // 8-bit entries may be packed two per word
+        let data = match bits <= 8 && data.len() < nb_entries && data.len() * 2 >= nb_entries {
+            true => data.iter().flat_map(|word| word.to_le_bytes().to_vec()).map(u16::from).collect(),
+            false => data
+        };
+
+        if data.len() < nb_entries {
+            return Err(format!("too short: {} entries, expected {}", data.len(), nb_entries))
+        }
+
+        Ok(Lut { first_mapped, bits, data: data[..nb_entries].to_vec() })
+    }
+
+    /// Values outside of the table are mapped to its first or last entry
+    pub fn get(&self, value: i64) -> u16 {
+        let index = value.saturating_sub(self.first_mapped).clamp(0, self.data.len() as i64 - 1);
+        self.data[index as usize]
+    }
+}
+
+/// Number of entries, first mapped value and entry size in bits
+fn get_descriptor(item: &DicomItem, tag: Tag, signed: bool) -> Result<(usize, i64, u32)> {
+
+    let descriptor = get_multi_int(item, tag)?.ok_or(DecodeError::MissingAttribute(tag))?;
+
+    if descriptor.len() != 3 {
+        return Err(DecodeError::invalid_attribute(tag, format!("bad descriptor {:?}", descriptor)))
+    }
+
+    // The descriptor may be read as unsigned whatever the pixel representation
+    let nb_entries = match descriptor[0] {
+        0 => 65536,
+        n => n.try_into().map_err(|_| DecodeError::invalid_attribute(tag, format!("bad size {}", n)))?
+    };
+    let first_mapped = match signed && descriptor[1] > 0x7FFF {
+        true => descriptor[1] - 0x10000,
+        false => descriptor[1]
+    };
+    let bits = match descriptor[2] {
+        bits @ 1..=16 => bits as u32,
+        bits => return Err(DecodeError::invalid_attribute(tag, format!("bad entry size: {} bits", bits)))
+    };
+
+    Ok((nb_entries, first_mapped, bits))
+}
+
+fn get_words(item: &DicomItem, tag: Tag) -> Result<Option<Vec<u16>>> {
+
+    let elem = match item.element(tag) {
+        Ok(elem) => elem,
+        Err(_) => return Ok(None)
+    };
+
+    let words = match elem.value().primitive() {
+        Some(PrimitiveValue::U16(words)) => words.to_vec(),
+        // Unknown VR, raw little endian words
+        Some(PrimitiveValue::U8(bytes)) => bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect(),
+        val => return Err(DecodeError::invalid_attribute(tag, format!("unexpected data type: {:?}", val)))
+    };
+
+    Ok(Some(words))
+}
+
+/// Expands Segmented Palette Color LUT Data (PS3.3 C.7.9.2): discrete segments list
+/// their entries, linear segments ramp from the last entry, indirect segments repeat
+/// earlier segments found at a word offset.
+fn expand_segments(segments: &[u16], lut: &mut Vec<u16>) -> Result<(), String> {
+
+    let word = |i: usize| -> Result<u16, String> {
+        segments.get(i).copied().ok_or_else(|| format!("truncated segment at word {}", i))
+    };
+
+    let mut i = 0;
+
+    while i < segments.len() {
+
+        let opcode = word(i)?;
+        let length = usize::from(word(i + 1)?);
+
+        match opcode {
+
+            // Discrete
+            0 => {
+                if i + 2 + length > segments.len() {
+                    return Err(format!("truncated discrete segment at word {}", i))
+                }
+                lut.extend_from_slice(&segments[i + 2..i + 2 + length]);
+                i += 2 + length;
+            },
+
+            // Linear
+            1 => {
+                let y0 = f64::from(*lut.last().ok_or("linear segment without previous entry")?);
+                let y1 = f64::from(word(i + 2)?);
+                for j in 1..=length {
+                    let y = y0 + (y1 - y0) * j as f64 / length as f64;
+                    lut.push(y.round() as u16);
+                }
+                i += 3;
+            },
+
+            // Indirect
+            2 => {
+                let offset = usize::from(word(i + 2)?) | usize::from(word(i + 3)?) << 16;
+                if offset >= i {
+                    return Err(format!("bad indirect segment offset: {}", offset))
+                }
+                let mut j = offset;
+                for _ in 0..length {
+                    let segment_len = match word(j)? {
+                        0 => 2 + usize::from(word(j + 1)?),
+                        1 => 3,
+                        2 => return Err(format!("nested indirect segment at word {}", j)),
+                        opcode => return Err(format!("bad segment opcode: {}", opcode))
+                    };
+                    if j + segment_len > i {
+                        return Err(format!("indirect segment overlaps itself at word {}", j))
+                    }
+                    expand_segments(&segments[j..j + segment_len], lut)?;
+                    j += segment_len;
+                }
+                i += 4;
+            },
+
+            opcode => return Err(format!("bad segment opcode: {}", opcode))
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Numeric value of a decimal string element, if present
+pub fn get_decimal(item: &DicomItem, tag: Tag) -> Result<Option<f64>> {
+    match get_str(item, tag)? {
+        Some(value) => parse_decimal(&value)
+            .map(Some)
+            .ok_or_else(|| DecodeError::invalid_attribute(tag, format!("bad decimal string {:?}", value))),
+        None => Ok(None)
+    }
+}
+
+/// First value of a possibly multi-valued decimal string. "inf", "NaN" and values
+/// past the f64 range parse, but are not valid decimal strings.
+fn parse_decimal(value: &str) -> Option<f64> {
+    let first = value.split('\\').next().unwrap_or_default();
+    let first = first.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+    first.parse().ok().filter(|v: &f64| v.is_finite())
+}
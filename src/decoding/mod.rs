@@ -1,139 +1,560 @@
 use std::convert::TryInto;
-use jpeg2000::decode::{Codec, DecodeConfig};
-use anyhow::{Result, anyhow};
-use crate::utils::{Dicom, EncodedImage, RawImage, Encoding, Format, Palettes};
+use crate::utils::{ColorSpace, Dicom, EncodedImage, RawImage, Encoding, Format, Palettes, auto_range_to_8bit};
 
+mod error;
+mod codec;
 mod dicom_parsing;
-use dicom_parsing::get_encoded_image_data;
-
-
+mod jpeg;
+mod openjpeg;
+mod lut;
+mod modality;
+mod voi;
+use dicom_parsing::{get_encoded_image_data, is_inverted};
+pub use error::{DecodeError, Result};
+pub use codec::{PixelCodec, register_codec, get_codec};
+pub use dicom_parsing::{get_int, get_opt_int, get_multi_int, get_str, open_dicom};
+pub use lut::Lut;
+pub use modality::ModalityLut;
+pub use voi::VoiLut;
+
+
+/// Decodes the first frame
 pub fn get_image(dicom: &Dicom) -> Result<RawImage> {
     let encoded_image = get_encoded_image_data(dicom)?;
-    let image = decode_image(&encoded_image)?;
-    Ok(image)
+    decode_image(&encoded_image, 0)
+}
+
+pub fn get_frames(dicom: &Dicom) -> Result<Vec<RawImage>> {
+    let encoded_image = get_encoded_image_data(dicom)?;
+    (0..encoded_image.frames.len())
+        .map(|i| decode_image(&encoded_image, i)
+            .map_err(|e| DecodeError::Frame { index: i, source: Box::new(e) }))
+        .collect()
+}
+
+/// Native frames with all their allocated bits, overlays may be embedded in the bits
+/// outside of Bits Stored.
+pub fn get_allocated_frames(dicom: &Dicom) -> Result<Vec<RawImage>> {
+
+    let EncodedImage { target_format, encoding, frames, .. } = get_encoded_image_data(dicom)?;
+
+    frames.iter()
+        .map(|bytes| {
+            let bytes = match encoding {
+                Encoding::RAW => bytes.clone(),
+                Encoding::RAWBigEndian => swap_bytes(bytes, target_format.channel_depth)?,
+                _ => return Err(DecodeError::unsupported("Pixel data is not native"))
+            };
+            Ok(RawImage { format: target_format.clone(), bytes })
+        })
+        .collect()
+}
+
+/// Decodes every frame to 8-bit samples for display. Grayscale frames go through the
+/// modality and VOI LUTs and are inverted if needed, color frames keep their most
+/// significant bits.
+pub fn get_display_frames(dicom: &Dicom) -> Result<Vec<RawImage>> {
+
+    let frames = get_frames(dicom)?;
+
+    if frames.iter().any(|frame| frame.format.channels != 1) {
+        return frames.iter().map(reduce_to_8bit).collect();
+    }
+
+    let modality_lut = ModalityLut::from_dicom(dicom)?;
+    let modality_frames = frames.iter()
+        .map(|frame| modality_lut.apply(frame))
+        .collect::<Result<Vec<_>>>()?;
+
+    let voi_lut = match VoiLut::from_dicom(dicom)? {
+        Some(voi_lut) => voi_lut,
+        None => VoiLut::from_range(&modality_frames)
+    };
+
+    let inverted = is_inverted(dicom)?;
+
+    modality_frames.iter()
+        .map(|frame| {
+            let mut display_frame = voi_lut.apply(frame)?;
+            if inverted {
+                display_frame.bytes.iter_mut().for_each(|v| *v = 255 - *v);
+            }
+            Ok(display_frame)
+        })
+        .collect()
 }
 
+fn reduce_to_8bit(image: &RawImage) -> Result<RawImage> {
 
-fn decode_image(encoded_image: &EncodedImage) -> Result<RawImage> {
+    let RawImage { format, bytes } = image;
+
+    if format.float {
+        let bytes = auto_range_to_8bit(format, bytes)?;
+        let format = Format { channel_depth: 1, signed: false, float: false, ..format.clone() };
+        return Ok(RawImage { format, bytes });
+    }
+
+    let bytes = match format.channel_depth {
+        1 => bytes.clone(),
+        2 => bytes.chunks_exact(2).map(|sample| sample[1]).collect(),
+        _ => return Err(DecodeError::unsupported(format!(
+            "Unsupported bit depth: {} (1 and 2 supported)",
+            format.channel_depth
+        )))
+    };
+
+    let format = Format { channel_depth: 1, signed: false, ..format.clone() };
+
+    Ok(RawImage { format, bytes })
+}
+
+
+fn decode_image(encoded_image: &EncodedImage, frame_index: usize) -> Result<RawImage> {
 
     let EncodedImage { 
-        target_format, encoding, palettes, bytes
+        target_format, encoding, palettes, color_space, planar, bits_stored, high_bit, frames
     } = encoded_image;
 
+    let bytes = frames.get(frame_index)
+        .ok_or_else(|| DecodeError::dimensions(format!("No frame {} in {} frames", frame_index + 1, frames.len())))?;
+
     let mut format = target_format.clone();
     let mut decoded_bytes = match encoding {
         Encoding::RAW => bytes.clone(),
-        Encoding::RLE => decode_RLE(&encoded_image)?,
-        Encoding::JPEG => decode_JPEG(&encoded_image)?,
-        Encoding::JPEG2000 => decode_JPEG2000(&encoded_image)?
+        Encoding::RAWBigEndian => swap_bytes(bytes, format.channel_depth)?,
+        Encoding::Encapsulated(codec) => codec.decode(bytes, &format)?
     };
 
+    // Codecs usually only output the stored bits
+    let allocated_bits = match encoding {
+        Encoding::Encapsulated(codec) => codec.keeps_allocated_bits(),
+        _ => true
+    };
+
+    if allocated_bits && !format.float {
+        mask_stored_bits(&mut decoded_bytes, format.channel_depth, *bits_stored, *high_bit)?;
+    }
+
+    if format.signed {
+        sign_extend(&mut decoded_bytes, format.channel_depth, *bits_stored)?;
+    }
+
+    if *planar {
+        decoded_bytes = interleave_planes(&decoded_bytes, format.channels, format.channel_depth)?;
+    }
+
+    /*
+        Color space
+    */
+
+    // Only native data is subsampled, codecs output full resolution chroma
+    let native_422 = matches!(encoding, Encoding::RAW | Encoding::RAWBigEndian)
+        && matches!(color_space, ColorSpace::YBRFull422 | ColorSpace::YBRPartial422);
+
+    if native_422 {
+        decoded_bytes = upsample_422(&decoded_bytes, format.channel_depth)?;
+    }
+
+    let converted_by_codec = match encoding {
+        Encoding::Encapsulated(codec) => codec.converts_color(*color_space),
+        _ => false
+    };
+
+    if format.channels == 3 && !converted_by_codec {
+        decoded_bytes = match color_space {
+            ColorSpace::YBRFull | ColorSpace::YBRFull422 => ybr_to_rgb(&decoded_bytes, format.channel_depth, false)?,
+            ColorSpace::YBRPartial422 => ybr_to_rgb(&decoded_bytes, format.channel_depth, true)?,
+            _ => decoded_bytes
+        };
+    }
+
     if let Some(ref palettes) = palettes {
-        decoded_bytes = map_to_palette(&decoded_bytes, palettes, format.channel_depth)?;
+        decoded_bytes = map_to_palette(&decoded_bytes, palettes, format.channel_depth, format.signed)?;
         format.channels = 3;
         format.channel_depth = 2;
+        format.signed = false;
+        format.float = false;
     }
 
     Ok(RawImage { format, bytes: decoded_bytes })
 }
 
-#[allow(non_snake_case)]
-fn decode_JPEG(encoded_image: &EncodedImage) -> Result<Vec<u8>> {
+/// Planar Configuration 1 stores each channel as a separate plane (RRR...GGG...BBB...)
+fn interleave_planes(bytes: &[u8], channels: u32, channel_depth: u32) -> Result<Vec<u8>> {
 
-    let mut decoder = jpeg_decoder::Decoder::new(encoded_image.bytes.as_slice());
-    let decoded_pixel_data = decoder.decode()?;
-    Ok(decoded_pixel_data)
+    let channels: usize = channels.try_into()?;
+    let d: usize = channel_depth.try_into()?;
+    let plane_len = bytes.len() / channels;
+
+    if plane_len == 0 || plane_len * channels != bytes.len() || !plane_len.is_multiple_of(d) {
+        return Err(DecodeError::dimensions(format!(
+            "Planar configuration: {} bytes cannot be split into {} planes", bytes.len(), channels
+        )))
+    }
+
+    let planes: Vec<&[u8]> = bytes.chunks_exact(plane_len).collect();
+
+    let interleaved = (0..plane_len / d)
+        .flat_map(|i| planes.iter().flat_map(move |plane| &plane[i * d..(i + 1) * d]))
+        .copied()
+        .collect();
+
+    Ok(interleaved)
 }
 
-#[allow(non_snake_case)]
-fn decode_JPEG2000(encoded_image: &EncodedImage) -> Result<Vec<u8>> {
+/// Native 4:2:2 data stores Y1 Y2 Cb Cr for each pair of pixels, chroma samples are repeated
+fn upsample_422(bytes: &[u8], channel_depth: u32) -> Result<Vec<u8>> {
+
+    let d: usize = channel_depth.try_into()?;
+
+    let upsampled = bytes
+        .chunks_exact(4 * d)
+        .flat_map(|pair| {
+            let (y1, y2, cb, cr) = (&pair[..d], &pair[d..2 * d], &pair[2 * d..3 * d], &pair[3 * d..]);
+            [y1, cb, cr, y2, cb, cr].concat()
+        })
+        .collect();
+
+    Ok(upsampled)
+}
+
+/// ITU-R BT.601 conversion, full range or partial range (Y in 16..235, Cb and Cr in 16..240 for 8 bits)
+fn ybr_to_rgb(bytes: &[u8], channel_depth: u32, partial: bool) -> Result<Vec<u8>> {
+
+    let max = match channel_depth {
+        1 => 255.0,
+        2 => 65535.0,
+        _ => return Err(DecodeError::unsupported(format!(
+            "YBR: unsupported bit depth: {} (1 and 2 supported)",
+            channel_depth
+        )))
+    };
+
+    let scale = (max + 1.0) / 256.0;
+    let d: usize = channel_depth.try_into()?;
+
+    let read = |sample: &[u8]| -> f64 {
+        match sample {
+            [v] => f64::from(*v),
+            _ => f64::from(u16::from_le_bytes([sample[0], sample[1]]))
+        }
+    };
+
+    let mut rgb_bytes = Vec::with_capacity(bytes.len());
+
+    for pixel in bytes.chunks_exact(3 * d) {
+
+        let (y, cb, cr) = (read(&pixel[..d]), read(&pixel[d..2 * d]), read(&pixel[2 * d..]));
+
+        let (y, cb, cr) = match partial {
+            true => (
+                (y - 16.0 * scale) * 255.0 / 219.0,
+                (cb - 128.0 * scale) * 255.0 / 224.0,
+                (cr - 128.0 * scale) * 255.0 / 224.0
+            ),
+            false => (y, cb - 128.0 * scale, cr - 128.0 * scale)
+        };
+
+        let r = y + 1.402 * cr;
+        let g = y - 0.344136 * cb - 0.714136 * cr;
+        let b = y + 1.772 * cb;
+
+        for v in [r, g, b].iter() {
+            let v = v.round().clamp(0.0, max);
+            match channel_depth {
+                1 => rgb_bytes.push(v as u8),
+                _ => rgb_bytes.extend_from_slice(&(v as u16).to_le_bytes())
+            }
+        }
+    }
+
+    Ok(rgb_bytes)
+}
 
-    let Format { channels, channel_depth, .. } = encoded_image.target_format;
+/// Keeps the Bits Stored bits ending at High Bit, the other bits of the allocated
+/// sample may hold overlays or garbage.
+fn mask_stored_bits(bytes: &mut [u8], channel_depth: u32, bits_stored: u32, high_bit: u32) -> Result<()> {
 
-    let dynamic_image = jpeg2000::decode::from_memory(
-        encoded_image.bytes.as_slice(),
-        Codec::JP2,
-        DecodeConfig {
-            default_colorspace: None,
-            discard_level: 0,
+    if !matches!(channel_depth, 1 | 2 | 4) {
+        return Err(DecodeError::unsupported(format!(
+            "Unsupported bit depth: {} (1, 2 and 4 supported)",
+            channel_depth
+        )))
+    }
+
+    let shift = high_bit + 1 - bits_stored;
+
+    if shift == 0 && bits_stored >= 8 * channel_depth {
+        return Ok(());
+    }
+
+    let mask = match bits_stored {
+        32 => u32::MAX,
+        _ => (1u32 << bits_stored) - 1
+    };
+
+    match channel_depth {
+        1 => for sample in bytes.iter_mut() {
+            *sample = ((u32::from(*sample) >> shift) & mask) as u8;
         },
-        None,
-    )?;
-
-    let pixel_data: Vec<u8> = match (channels, channel_depth) {
-        (1, 1) => dynamic_image.to_luma().into_vec(),
-        (3, 1) => dynamic_image.to_rgb().into_vec(),
-        (4, 1) => dynamic_image.to_rgba().into_vec(),
-        _ => return Err(anyhow!(
-            "JPEG2000 output is unsupported: {} channels of depth {} bytes",
-            channels, channel_depth
-        ))
+        2 => for sample in bytes.chunks_exact_mut(2) {
+            let value = u32::from(u16::from_le_bytes([sample[0], sample[1]]));
+            sample.copy_from_slice(&(((value >> shift) & mask) as u16).to_le_bytes());
+        },
+        _ => for sample in bytes.chunks_exact_mut(4) {
+            let value = u32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]);
+            sample.copy_from_slice(&((value >> shift) & mask).to_le_bytes());
+        }
     };
 
-    Ok(pixel_data)
+    Ok(())
 }
 
-fn map_to_palette(bytes: &Vec<u8>, palettes: &Palettes, channel_depth: u32) -> Result<Vec<u8>> {
+/// Extends the sign bit of Bits Stored bits samples to the whole channel
+fn sign_extend(bytes: &mut [u8], channel_depth: u32, bits_stored: u32) -> Result<()> {
+
+    if !matches!(channel_depth, 1 | 2 | 4) {
+        return Err(DecodeError::unsupported(format!(
+            "Unsupported bit depth: {} (1, 2 and 4 supported)",
+            channel_depth
+        )))
+    }
+
+    if bits_stored >= 8 * channel_depth {
+        return Ok(());
+    }
+
+    let shift = 32 - bits_stored;
+    let extend = |value: u32| ((value << shift) as i32 >> shift) as u32;
 
     match channel_depth {
-        1 | 2 => (),
-        _ => return Err(anyhow!(
+        1 => for sample in bytes.iter_mut() {
+            *sample = extend(u32::from(*sample)) as u8;
+        },
+        2 => for sample in bytes.chunks_exact_mut(2) {
+            let value = u32::from(u16::from_le_bytes([sample[0], sample[1]]));
+            sample.copy_from_slice(&(extend(value) as u16).to_le_bytes());
+        },
+        _ => for sample in bytes.chunks_exact_mut(4) {
+            let value = u32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]);
+            sample.copy_from_slice(&extend(value).to_le_bytes());
+        }
+    };
+
+    Ok(())
+}
+
+fn swap_bytes(bytes: &[u8], channel_depth: u32) -> Result<Vec<u8>> {
+
+    match channel_depth {
+        1 => return Ok(bytes.to_vec()),
+        2 | 4 | 8 => (),
+        _ => return Err(DecodeError::unsupported(format!(
+            "Big endian: unsupported bit depth: {} (1, 2, 4 and 8 supported)",
+            channel_depth
+        )))
+    };
+
+    let d: usize = channel_depth.try_into()?;
+    let swapped_bytes: Vec<u8> = bytes
+        .chunks_exact(d)
+        .flat_map(|sample| sample.iter().rev().copied().collect::<Vec<u8>>())
+        .collect();
+
+    Ok(swapped_bytes)
+}
+
+/// Rejects frames declaring more samples than their encoded bytes can hold before
+/// allocating them, `max_per_byte` is the most samples a byte of the codec decodes to.
+fn check_encoded_size(codec: &str, encoded_len: usize, nb_samples: u64, max_per_byte: u64) -> Result<()> {
+
+    if nb_samples > encoded_len as u64 * max_per_byte {
+        return Err(DecodeError::corrupt(format!(
+            "{}: {} bytes cannot hold {} samples", codec, encoded_len, nb_samples
+        )))
+    }
+
+    Ok(())
+}
+
+#[allow(non_snake_case)]
+fn decode_JPEG(bytes: &[u8], format: &Format) -> Result<Vec<u8>> {
+
+    // Every 8x8 block has at least a DC and an end of block code
+    check_encoded_size("JPEG", bytes.len(), u64::from(format.w) * u64::from(format.h), 4 * 64)?;
+
+    // jpeg_decoder only supports 8-bit samples, 12-bit Extended (Process 4) images
+    // are handled by our own decoder
+    let decoded_image = match jpeg::sample_precision(bytes) {
+
+        Some(precision) if precision > 8 => jpeg::decode_extended(bytes, (format.w, format.h))?,
+
+        _ => {
+            let mut decoder = jpeg_decoder::Decoder::new(bytes);
+            decoder.read_info().map_err(|e| DecodeError::corrupt(format!("JPEG: {}", e)))?;
+            let info = decoder.info().ok_or_else(|| DecodeError::corrupt("JPEG: missing image info"))?;
+            if (u32::from(info.width), u32::from(info.height)) != (format.w, format.h) {
+                return Err(DecodeError::dimensions(format!(
+                    "JPEG: image is {}x{}, expected {}x{}", info.width, info.height, format.w, format.h
+                )))
+            }
+            let decoded_pixel_data = decoder.decode()
+                .map_err(|e| DecodeError::corrupt(format!("JPEG: {}", e)))?;
+            let channels = match info.pixel_format {
+                jpeg_decoder::PixelFormat::L8 => 1,
+                jpeg_decoder::PixelFormat::RGB24 => 3,
+                jpeg_decoder::PixelFormat::CMYK32 => 4
+            };
+            jpeg::DecodedImage {
+                w: info.width.into(),
+                h: info.height.into(),
+                channels,
+                samples: decoded_pixel_data.into_iter().map(u16::from).collect()
+            }
+        }
+    };
+
+    check_decoded_image("JPEG", &decoded_image, format)?;
+    decoded_image.to_bytes(format.channel_depth)
+}
+
+#[allow(non_snake_case)]
+fn decode_JPEG_lossless(bytes: &[u8], format: &Format) -> Result<Vec<u8>> {
+
+    // Every sample has at least a 1-bit difference code
+    let nb_samples = u64::from(format.w) * u64::from(format.h) * u64::from(format.channels);
+    check_encoded_size("JPEG Lossless", bytes.len(), nb_samples, 8)?;
+
+    let decoded_image = jpeg::decode_lossless(bytes, (format.w, format.h))?;
+
+    check_decoded_image("JPEG Lossless", &decoded_image, format)?;
+    decoded_image.to_bytes(format.channel_depth)
+}
+
+#[allow(non_snake_case)]
+fn decode_JPEG_LS(bytes: &[u8], format: &Format) -> Result<Vec<u8>> {
+
+    // A bit of run mode codes at most 2^15 pixels
+    check_encoded_size("JPEG-LS", bytes.len(), u64::from(format.w) * u64::from(format.h), 8 << 15)?;
+
+    let decoded_image = jpeg::decode_ls(bytes, (format.w, format.h))?;
+
+    check_decoded_image("JPEG-LS", &decoded_image, format)?;
+    decoded_image.to_bytes(format.channel_depth)
+}
+
+fn check_decoded_image(codec: &str, decoded_image: &jpeg::DecodedImage, format: &Format) -> Result<()> {
+
+    let Format { w, h, channels, .. } = *format;
+
+    if (decoded_image.w, decoded_image.h, decoded_image.channels) != (w, h, channels) {
+        return Err(DecodeError::dimensions(format!(
+            "{}: decoded image is {}x{} with {} channels, expected {}x{} with {} channels",
+            codec, decoded_image.w, decoded_image.h, decoded_image.channels, w, h, channels
+        )))
+    }
+
+    Ok(())
+}
+
+#[allow(non_snake_case)]
+fn decode_JPEG2000(bytes: &[u8], format: &Format) -> Result<Vec<u8>> {
+
+    let decoded_image = openjpeg::decode("JPEG2000", bytes, (format.w, format.h))?;
+
+    check_decoded_image("JPEG2000", &decoded_image, format)?;
+    decoded_image.to_bytes(format.channel_depth)
+}
+
+#[allow(non_snake_case)]
+fn decode_HTJ2K(bytes: &[u8], format: &Format) -> Result<Vec<u8>> {
+
+    let decoded_image = openjpeg::decode("HTJ2K", bytes, (format.w, format.h))?;
+
+    check_decoded_image("HTJ2K", &decoded_image, format)?;
+    decoded_image.to_bytes(format.channel_depth)
+}
+
+/// Palette entries are output as 16-bit samples, 8-bit entries are scaled up
+fn map_to_palette(bytes: &[u8], palettes: &Palettes, channel_depth: u32, signed: bool) -> Result<Vec<u8>> {
+
+    let d: usize = match channel_depth {
+        1 | 2 => channel_depth.try_into()?,
+        _ => return Err(DecodeError::unsupported(format!(
             "Unsupported bit depth: {} (1 and 2 supported)",
             channel_depth
-        )) 
+        )))
     };
 
-    let d: usize = channel_depth.try_into().unwrap();
     let mapped_bytes: Vec<u8> = bytes
         .chunks_exact(d)
-        .map(|v| -> usize { match v {
-            [b0] => u8::from_be_bytes([*b0]).into(),
-            [b0, b1] => u16::from_be_bytes([*b0, *b1]).into(),
-            _ => panic!()
+        .map(|v| -> i64 { match (v, signed) {
+            ([b0], false) => u8::from_le_bytes([*b0]).into(),
+            ([b0], true) => i8::from_le_bytes([*b0]).into(),
+            (b, false) => u16::from_le_bytes([b[0], b[1]]).into(),
+            (b, true) => i16::from_le_bytes([b[0], b[1]]).into()
         }})
         .flat_map(|index| {
-
-            let [r0, r1] = palettes[0][index].to_le_bytes();
-            let [g0, g1] = palettes[1][index].to_le_bytes();
-            let [b0, b1] = palettes[2][index].to_le_bytes();
-
-            vec![r0, r1, g0, g1, b0, b1]
+            palettes.iter().flat_map(move |palette| {
+                let max = (1u32 << palette.bits) - 1;
+                let entry = u32::from(palette.get(index)).min(max) * 65535 / max;
+                (entry as u16).to_le_bytes().to_vec()
+            })
         })
         .collect();
 
     Ok(mapped_bytes)
 }
 
+/// Each RLE segment holds one byte of one channel, most significant byte first:
+/// R MSB, R LSB, G MSB, G LSB... Segments are interleaved back to little endian samples.
 #[allow(non_snake_case)]
-fn decode_RLE(encoded_data: &EncodedImage) -> Result<Vec<u8>> {
+fn decode_RLE(bytes: &[u8], format: &Format) -> Result<Vec<u8>> {
 
-    let EncodedImage { bytes, .. } = encoded_data;
+    let channels: usize = format.channels.try_into()?;
+    let d: usize = format.channel_depth.try_into()?;
+    let nb_pixels: usize = (u64::from(format.w) * u64::from(format.h)).try_into()?;
 
     /*
         Decoding segments
     */
 
-    let mut offsets = decode_header(&bytes)?;
+    let mut offsets = decode_header(bytes)?;
+
+    if offsets.len() != channels * d {
+        return Err(DecodeError::corrupt(format!(
+            "RLE: {} segments, expected {} for {} channels of {} bytes",
+            offsets.len(), channels * d, channels, d
+        )))
+    }
+
     offsets.push(bytes.len());
-    let nb_segments = offsets.len();
 
     let mut decoded_segments = Vec::new();
-    for i in 0..nb_segments-1 {
+    for i in 0..offsets.len()-1 {
         let o1 = offsets[i];
         let o2 = offsets[i+1];
-        let segment_data = &bytes[o1..o2];
-        decoded_segments.push(decode_segment(segment_data)?);
+        if o1 > o2 || o2 > bytes.len() {
+            return Err(DecodeError::corrupt(format!("RLE: bad offset for segment {}: {}", i + 1, o1)))
+        }
+        // A PackBits byte pair expands to at most 128 bytes
+        check_encoded_size(&format!("RLE: segment {}", i + 1), o2 - o1, nb_pixels as u64, 128)?;
+        let mut decoded_segment = decode_segment(&bytes[o1..o2])?;
+        // Segments may be padded with an extra byte, or be too short in broken files
+        decoded_segment.resize(nb_pixels, 0);
+        decoded_segments.push(decoded_segment);
     }
 
     /*
         Interlacing segments
     */
 
-    let seg_len = decoded_segments[0].len();
-    let mut decoded_pixel_data = Vec::new();
-    for i in 0..seg_len {
-        for segment_data in decoded_segments.iter() {
-            decoded_pixel_data.push(segment_data[i]);
+    let mut decoded_pixel_data = Vec::with_capacity(nb_pixels * channels * d);
+    for i in 0..nb_pixels {
+        for channel_segments in decoded_segments.chunks_exact(d) {
+            for segment_data in channel_segments.iter().rev() {
+                decoded_pixel_data.push(segment_data[i]);
+            }
         }
     }
 
@@ -141,24 +562,27 @@ fn decode_RLE(encoded_data: &EncodedImage) -> Result<Vec<u8>> {
 }
 
 
-fn decode_header(pixel_data: &Vec<u8>) -> Result<Vec<usize>> {
+fn decode_header(pixel_data: &[u8]) -> Result<Vec<usize>> {
 
     if pixel_data.len() < 64 {
-        return Err(anyhow!("RLE: not enough bytes for header"))
+        return Err(DecodeError::corrupt("RLE: not enough bytes for header"))
     }
 
-    let nb_segments = u32::from_le_bytes((&pixel_data[..4]).try_into()?);
+    let nb_segments = u32::from_le_bytes([pixel_data[0], pixel_data[1], pixel_data[2], pixel_data[3]]);
 
     if nb_segments == 0 {
-        return Err(anyhow!("RLE: header has zero segments"))
+        return Err(DecodeError::corrupt("RLE: header has zero segments"))
+    }
+
+    // The 64-byte header has room for 15 segment offsets
+    if nb_segments > 15 {
+        return Err(DecodeError::corrupt(format!("RLE: header has {} segments (15 at most)", nb_segments)))
     }
 
     let mut offsets = Vec::new();
-    let n = std::cmp::min(nb_segments, 16);
-    for i in 1..n+1 {
-        let x = (i * 4).try_into().unwrap();
-        let o = u32::from_le_bytes((&pixel_data[x..x+4]).try_into()?);
-        offsets.push(o.try_into().unwrap());
+    for i in 1..=nb_segments as usize {
+        let o = u32::from_le_bytes([pixel_data[4 * i], pixel_data[4 * i + 1], pixel_data[4 * i + 2], pixel_data[4 * i + 3]]);
+        offsets.push(o.try_into()?);
     }
 
     Ok(offsets)
@@ -0,0 +1,105 @@
+use dicom::object::Tag;
+
+use crate::utils::{Dicom, DicomItem, Format, ModalityImage, RawImage, float_samples};
+use super::error::{DecodeError, Result};
+use super::dicom_parsing::get_opt_int;
+use super::lut::{Lut, get_decimal};
+
+
+/// Transform from stored values to physical values
+pub enum ModalityLut {
+    Rescale { slope: f64, intercept: f64 },
+    Table(Lut)
+}
+
+impl ModalityLut {
+
+    /// Reads the Modality LUT Sequence (0028,3000) or Rescale Slope / Intercept, at the
+    /// top level or in the shared functional groups of enhanced images.
+    /// Without any of them, values are left unchanged.
+    pub fn from_dicom(dicom: &Dicom) -> Result<ModalityLut> {
+
+        let signed = get_opt_int::<u32>(dicom, Tag(0x0028, 0x0103))? == Some(1);
+
+        if let Some(lut) = ModalityLut::from_item(dicom, signed)? {
+            return Ok(lut);
+        }
+
+        // Shared Functional Groups Sequence > Pixel Value Transformation Sequence
+        let transformation = dicom.element(Tag(0x5200, 0x9229)).ok()
+            .and_then(|elem| elem.value().items()?.first())
+            .and_then(|group| group.element(Tag(0x0028, 0x9145)).ok())
+            .and_then(|elem| elem.value().items()?.first());
+
+        if let Some(item) = transformation {
+            if let Some(lut) = ModalityLut::from_item(item, signed)? {
+                return Ok(lut);
+            }
+        }
+
+        Ok(ModalityLut::Rescale { slope: 1.0, intercept: 0.0 })
+    }
+
+    fn from_item(item: &DicomItem, signed: bool) -> Result<Option<ModalityLut>> {
+
+        let lut_item = item.element(Tag(0x0028, 0x3000)).ok()
+            .and_then(|elem| elem.value().items()?.first());
+
+        if let Some(lut_item) = lut_item {
+            return Ok(Some(ModalityLut::Table(Lut::from_item(lut_item, signed)?)));
+        }
+
+        let slope = get_decimal(item, Tag(0x0028, 0x1053))?;
+        let intercept = get_decimal(item, Tag(0x0028, 0x1052))?;
+
+        let lut = match (slope, intercept) {
+            (None, None) => None,
+            (slope, intercept) => Some(ModalityLut::Rescale {
+                slope: slope.unwrap_or(1.0),
+                intercept: intercept.unwrap_or(0.0)
+            })
+        };
+
+        Ok(lut)
+    }
+
+    pub fn apply(&self, image: &RawImage) -> Result<ModalityImage> {
+
+        let Format { w, h, channels, .. } = image.format;
+
+        let values = match image.format.float {
+            true => float_samples(&image.format, &image.bytes)?,
+            false => stored_values(image)?.into_iter().map(|value| value as f64).collect()
+        };
+
+        let values = values
+            .into_iter()
+            .map(|value| match self {
+                ModalityLut::Rescale { slope, intercept } => (value * slope + intercept) as f32,
+                ModalityLut::Table(lut) => f32::from(lut.get(value.round() as i64))
+            })
+            .collect();
+
+        Ok(ModalityImage { w, h, channels, values })
+    }
+}
+
+
+fn stored_values(image: &RawImage) -> Result<Vec<i64>> {
+
+    let RawImage { format, bytes } = image;
+
+    let values = match (format.channel_depth, format.signed) {
+        (1, false) => bytes.iter().map(|v| i64::from(*v)).collect(),
+        (1, true) => bytes.iter().map(|v| i64::from(*v as i8)).collect(),
+        (2, false) => bytes.chunks_exact(2).map(|b| i64::from(u16::from_le_bytes([b[0], b[1]]))).collect(),
+        (2, true) => bytes.chunks_exact(2).map(|b| i64::from(i16::from_le_bytes([b[0], b[1]]))).collect(),
+        (4, false) => bytes.chunks_exact(4).map(|b| i64::from(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))).collect(),
+        (4, true) => bytes.chunks_exact(4).map(|b| i64::from(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))).collect(),
+        (depth, _) => return Err(DecodeError::unsupported(format!(
+            "Modality LUT: unsupported bit depth: {} (1, 2 and 4 supported)", depth
+        )))
+    };
+
+    Ok(values)
+}
@@ -0,0 +1,224 @@
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+use std::ptr::null_mut;
+use openjpeg_sys as ffi;
+use super::error::{DecodeError, Result};
+
+use super::jpeg::DecodedImage;
+
+
+const JP2_SIGNATURE: [u8; 12] = [0x00, 0x00, 0x00, 0x0C, 0x6A, 0x50, 0x20, 0x20, 0x0D, 0x0A, 0x87, 0x0A];
+const J2K_SOC: [u8; 2] = [0xFF, 0x4F];
+
+
+/// Decodes a JPEG 2000 image with OpenJPEG, keeping the full sample precision.
+/// DICOM fragments normally hold a bare J2K codestream, but some writers wrap
+/// it in a JP2 file. High-Throughput (HTJ2K) codestreams go through the same path,
+/// OpenJPEG picks the block decoder from the CAP marker.
+pub fn decode(codec_name: &str, data: &[u8], size: (u32, u32)) -> Result<DecodedImage> {
+
+    let codec_format = if data.starts_with(&JP2_SIGNATURE) {
+        ffi::CODEC_FORMAT::OPJ_CODEC_JP2
+    } else if data.starts_with(&J2K_SOC) {
+        ffi::CODEC_FORMAT::OPJ_CODEC_J2K
+    } else {
+        return Err(DecodeError::corrupt(format!(
+            "{}: data is neither a J2K codestream nor a JP2 file", codec_name
+        )))
+    };
+
+    let mut source = Source { data, pos: 0 };
+    let mut error_message = String::new();
+
+    unsafe {
+
+        /*
+            Stream and codec setup
+        */
+
+        let stream = Stream(ffi::opj_stream_default_create(1));
+        ffi::opj_stream_set_read_function(stream.0, Some(read_fn));
+        ffi::opj_stream_set_skip_function(stream.0, Some(skip_fn));
+        ffi::opj_stream_set_seek_function(stream.0, Some(seek_fn));
+        ffi::opj_stream_set_user_data(stream.0, &mut source as *mut Source as *mut c_void, None);
+        ffi::opj_stream_set_user_data_length(stream.0, data.len() as u64);
+
+        let codec = Codec(ffi::opj_create_decompress(codec_format));
+        if codec.0.is_null() {
+            return Err(DecodeError::corrupt(format!("{}: cannot create decoder", codec_name)))
+        }
+        ffi::opj_set_error_handler(codec.0, Some(error_handler), &mut error_message as *mut String as *mut c_void);
+
+        let mut parameters: ffi::opj_dparameters_t = std::mem::zeroed();
+        ffi::opj_set_default_decoder_parameters(&mut parameters);
+        if ffi::opj_setup_decoder(codec.0, &mut parameters) != 1 {
+            return Err(DecodeError::corrupt(format!("{}: cannot set up decoder", codec_name)))
+        }
+
+        /*
+            Decoding
+        */
+
+        let mut image = Image(null_mut());
+
+        if ffi::opj_read_header(stream.0, codec.0, &mut image.0) != 1 {
+            return Err(DecodeError::corrupt(format!(
+                "{}: cannot read header: {}", codec_name, error_message
+            )))
+        }
+
+        // Corrupt headers could make OpenJPEG allocate huge images
+        let header = &*image.0;
+        let header_size = (header.x1.checked_sub(header.x0), header.y1.checked_sub(header.y0));
+        if header_size != (Some(size.0), Some(size.1)) {
+            return Err(DecodeError::dimensions(format!(
+                "{}: image area ({}, {}) to ({}, {}), expected {}x{}",
+                codec_name, header.x0, header.y0, header.x1, header.y1, size.0, size.1
+            )))
+        }
+
+        if ffi::opj_decode(codec.0, stream.0, image.0) != 1 || ffi::opj_end_decompress(codec.0, stream.0) != 1 {
+            return Err(DecodeError::corrupt(format!("{}: decoding failed: {}", codec_name, error_message)))
+        }
+
+        /*
+            Interleaving components
+        */
+
+        let img = &*image.0;
+        let w = img.x1 - img.x0;
+        let h = img.y1 - img.y0;
+
+        if img.numcomps == 0 || img.comps.is_null() {
+            return Err(DecodeError::corrupt(format!("{}: image has no components", codec_name)))
+        }
+
+        let components = std::slice::from_raw_parts(img.comps, img.numcomps as usize);
+
+        let mut planes = Vec::with_capacity(components.len());
+
+        for component in components.iter() {
+
+            if component.w != w || component.h != h {
+                return Err(DecodeError::unsupported(format!(
+                    "{}: subsampled components are not supported", codec_name
+                )))
+            }
+            if component.prec > 16 {
+                return Err(DecodeError::unsupported(format!(
+                    "{}: unsupported precision: {} bits", codec_name, component.prec
+                )))
+            }
+            if component.data.is_null() {
+                return Err(DecodeError::corrupt(format!("{}: missing component data", codec_name)))
+            }
+
+            let mask = ((1u32 << component.prec) - 1) as i32;
+            let plane: Vec<u16> = std::slice::from_raw_parts(component.data, (w * h) as usize)
+                .iter()
+                .map(|v| (v & mask) as u16) // Signed samples are kept in two's complement
+                .collect();
+
+            planes.push(plane);
+        }
+
+        let mut samples = Vec::with_capacity((w * h) as usize * planes.len());
+        for i in 0..(w * h) as usize {
+            for plane in planes.iter() {
+                samples.push(plane[i]);
+            }
+        }
+
+        Ok(DecodedImage { w, h, channels: planes.len() as u32, samples })
+    }
+}
+
+
+/*
+    Resource guards
+*/
+
+struct Stream(*mut ffi::opj_stream_t);
+struct Codec(*mut ffi::opj_codec_t);
+struct Image(*mut ffi::opj_image_t);
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        unsafe { ffi::opj_stream_destroy(self.0) }
+    }
+}
+
+impl Drop for Codec {
+    fn drop(&mut self) {
+        unsafe { ffi::opj_destroy_codec(self.0) }
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { ffi::opj_image_destroy(self.0) }
+        }
+    }
+}
+
+
+/*
+    In-memory stream callbacks
+*/
+
+struct Source<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+unsafe extern "C" fn read_fn(buffer: *mut c_void, nb_bytes: usize, user_data: *mut c_void) -> usize {
+
+    let source = &mut *(user_data as *mut Source);
+    let remaining = &source.data[source.pos..];
+
+    if remaining.is_empty() {
+        return usize::MAX; // End of stream
+    }
+
+    let n = nb_bytes.min(remaining.len());
+    std::ptr::copy_nonoverlapping(remaining.as_ptr(), buffer as *mut u8, n);
+    source.pos += n;
+
+    n
+}
+
+unsafe extern "C" fn skip_fn(nb_bytes: i64, user_data: *mut c_void) -> i64 {
+
+    let source = &mut *(user_data as *mut Source);
+
+    if nb_bytes < 0 {
+        return -1;
+    }
+
+    let n = (nb_bytes as usize).min(source.data.len() - source.pos);
+    source.pos += n;
+
+    n as i64
+}
+
+unsafe extern "C" fn seek_fn(pos: i64, user_data: *mut c_void) -> i32 {
+
+    let source = &mut *(user_data as *mut Source);
+
+    if pos < 0 || pos as usize > source.data.len() {
+        return 0;
+    }
+
+    source.pos = pos as usize;
+
+    1
+}
+
+unsafe extern "C" fn error_handler(message: *const c_char, user_data: *mut c_void) {
+    let error_message = &mut *(user_data as *mut String);
+    if !error_message.is_empty() {
+        error_message.push_str("; ");
+    }
+    error_message.push_str(CStr::from_ptr(message).to_string_lossy().trim_end());
+}
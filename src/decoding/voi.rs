@@ -0,0 +1,161 @@
+use dicom::object::Tag;
+
+use crate::utils::{Dicom, DicomItem, Format, ModalityImage, RawImage};
+use super::error::{DecodeError, Result};
+use super::dicom_parsing::{get_opt_int, get_str};
+use super::lut::{Lut, get_decimal};
+
+
+/// VOI LUT Function (0028,1056)
+#[derive(Debug, Clone, Copy)]
+pub enum VoiFunction {
+    Linear,
+    LinearExact,
+    Sigmoid
+}
+
+/// Transform from modality values to displayed values
+pub enum VoiLut {
+    Window { center: f64, width: f64, function: VoiFunction },
+    Table(Lut)
+}
+
+impl VoiLut {
+
+    /// Reads the first VOI LUT Sequence (0028,3010) table or Window Center / Width,
+    /// at the top level or in the shared functional groups of enhanced images.
+    pub fn from_dicom(dicom: &Dicom) -> Result<Option<VoiLut>> {
+
+        let signed = get_opt_int::<u32>(dicom, Tag(0x0028, 0x0103))? == Some(1);
+
+        if let Some(lut) = VoiLut::from_item(dicom, signed)? {
+            return Ok(Some(lut));
+        }
+
+        // Shared Functional Groups Sequence > Frame VOI LUT Sequence
+        let frame_voi = dicom.element(Tag(0x5200, 0x9229)).ok()
+            .and_then(|elem| elem.value().items()?.first())
+            .and_then(|group| group.element(Tag(0x0028, 0x9132)).ok())
+            .and_then(|elem| elem.value().items()?.first());
+
+        match frame_voi {
+            Some(item) => VoiLut::from_item(item, signed),
+            None => Ok(None)
+        }
+    }
+
+    fn from_item(item: &DicomItem, signed: bool) -> Result<Option<VoiLut>> {
+
+        let lut_item = item.element(Tag(0x0028, 0x3010)).ok()
+            .and_then(|elem| elem.value().items()?.first());
+
+        if let Some(lut_item) = lut_item {
+            return Ok(Some(VoiLut::Table(Lut::from_item(lut_item, signed)?)));
+        }
+
+        let center = get_decimal(item, Tag(0x0028, 0x1050))?;
+        let width = get_decimal(item, Tag(0x0028, 0x1051))?;
+
+        let (center, width) = match (center, width) {
+            (Some(center), Some(width)) => (center, width),
+            _ => return Ok(None)
+        };
+
+        let function = match get_str(item, Tag(0x0028, 0x1056))?.as_deref() {
+            Some("LINEAR") | None => VoiFunction::Linear,
+            Some("LINEAR_EXACT") => VoiFunction::LinearExact,
+            Some("SIGMOID") => VoiFunction::Sigmoid,
+            Some(val) => return Err(DecodeError::unsupported(format!("Unsupported VOI LUT function: {}", val)))
+        };
+
+        let valid_width = match function {
+            VoiFunction::Linear => width >= 1.0,
+            _ => width > 0.0
+        };
+        if !valid_width {
+            return Err(DecodeError::invalid_attribute(
+                Tag(0x0028, 0x1051), format!("bad window width for {:?}: {}", function, width)
+            ))
+        }
+
+        Ok(Some(VoiLut::Window { center, width, function }))
+    }
+
+    /// Window covering the whole range of values, for data sets without VOI
+    pub fn from_range(images: &[ModalityImage]) -> VoiLut {
+
+        let (min, max) = images.iter()
+            .flat_map(|image| image.values.iter())
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| (min.min(*v), max.max(*v)));
+
+        let (min, max) = match min <= max {
+            true => (f64::from(min), f64::from(max)),
+            false => (0.0, 0.0)
+        };
+
+        VoiLut::Window {
+            center: (min + max) / 2.0,
+            width: (max - min).max(1.0),
+            function: VoiFunction::LinearExact
+        }
+    }
+
+    /// Maps modality values to 8-bit samples
+    pub fn apply(&self, image: &ModalityImage) -> Result<RawImage> {
+
+        let ModalityImage { w, h, channels, values } = image;
+
+        if *channels != 1 {
+            return Err(DecodeError::unsupported(format!("VOI LUT: unsupported image with {} channels", channels)))
+        }
+
+        let bytes = values.iter()
+            .map(|value| {
+                let y = self.get(f64::from(*value));
+                (y * 255.0).round().clamp(0.0, 255.0) as u8
+            })
+            .collect();
+
+        let format = Format { w: *w, h: *h, channels: 1, channel_depth: 1, signed: false, float: false };
+
+        Ok(RawImage { format, bytes })
+    }
+
+    /// Displayed value between 0 and 1 (PS3.3 C.11.2.1.2)
+    fn get(&self, x: f64) -> f64 {
+        match self {
+
+            VoiLut::Window { center: c, width: w, function: VoiFunction::Linear } => {
+                if x <= c - 0.5 - (w - 1.0) / 2.0 {
+                    0.0
+                } else if x > c - 0.5 + (w - 1.0) / 2.0 {
+                    1.0
+                } else {
+                    (x - (c - 0.5)) / (w - 1.0) + 0.5
+                }
+            },
+
+            VoiLut::Window { center: c, width: w, function: VoiFunction::LinearExact } => {
+                if x <= c - w / 2.0 {
+                    0.0
+                } else if x > c + w / 2.0 {
+                    1.0
+                } else {
+                    (x - c) / w + 0.5
+                }
+            },
+
+            VoiLut::Window { center: c, width: w, function: VoiFunction::Sigmoid } => {
+                1.0 / (1.0 + (-4.0 * (x - c) / w).exp())
+            },
+
+            VoiLut::Table(lut) => {
+                let max = ((1u32 << lut.bits) - 1) as f64;
+                // Clamped to the input range before the conversion to an integer
+                let first = lut.first_mapped as f64;
+                let x = x.round().clamp(first, first + (lut.data.len() - 1) as f64);
+                f64::from(lut.get(x as i64)) / max
+            }
+        }
+    }
+}
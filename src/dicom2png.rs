@@ -1,12 +1,7 @@
-use std::path::PathBuf;
-use dicom::object::open_file;
+use std::path::{Path, PathBuf};
 use anyhow::{Result, anyhow};
 
-mod utils;
-mod decoding;
-
-use utils::write_image;
-use decoding::get_image;
+use oxydicom::{get_display_frames, get_frames, open_dicom, write_image};
 
 fn main() -> Result<()> {
 
@@ -17,9 +12,40 @@ fn main() -> Result<()> {
     let output_path = PathBuf::from(
         args.next().ok_or(anyhow!("Not enough arguments"))?);
 
-    let dicom = open_file(input_path.as_os_str())?;
-    let decoded_image_data = get_image(&dicom)?;
-    write_image(&decoded_image_data, output_path.as_path())?;
+    // --display writes 8-bit images as shown in the viewer, with the modality and VOI
+    // LUTs applied, instead of the stored values
+    let display = match args.next().as_deref() {
+        Some("--display") => true,
+        Some(arg) => return Err(anyhow!("Unknown argument: {}", arg)),
+        None => false
+    };
+
+    let dicom = open_dicom(&input_path)?;
+    let frames = match display {
+        true => get_display_frames(&dicom)?,
+        false => get_frames(&dicom)?
+    };
+
+    // Multi-frame images are written to numbered files: out_1.png, out_2.png...
+    for (i, frame) in frames.iter().enumerate() {
+        let frame_path = match frames.len() {
+            1 => output_path.clone(),
+            _ => numbered_path(&output_path, i + 1)
+        };
+        write_image(frame, frame_path.as_path())?;
+    }
 
     Ok(())
 }
+
+fn numbered_path(path: &Path, n: usize) -> PathBuf {
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let filename = match path.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, n)
+    };
+
+    path.with_file_name(filename)
+}
@@ -0,0 +1,19 @@
+//! DICOM decoding of OxyDICOM: opening files, decoding the frames of the supported
+//! transfer syntaxes to raw images, display LUTs, overlays and the tag table shown
+//! by the viewer.
+
+mod utils;
+mod decoding;
+mod dicom_table;
+mod overlay;
+
+pub use utils::{
+    Dicom, DicomItem, ColorSpace, Encoding, Format, RawImage, ModalityImage,
+    write_image, convert_to_BGRA8888
+};
+pub use decoding::{
+    open_dicom, get_image, get_frames, get_display_frames, get_allocated_frames,
+    register_codec, get_codec, PixelCodec, DecodeError, Lut, ModalityLut, VoiLut
+};
+pub use dicom_table::{TableEntry, get_dicom_table};
+pub use overlay::{Overlay, OverlayType, get_overlays, draw_overlays};
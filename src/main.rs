@@ -1,6 +1,5 @@
 use std::path::PathBuf;
 use std::fmt::Debug;
-use dicom::object::open_file;
 use iced::{
     Container, Element, Settings, Image, Row,
     Text, Scrollable, scrollable, Button, Column, button,
@@ -11,14 +10,12 @@ use iced::image::Handle;
 use clipboard::{ClipboardProvider, ClipboardContext};
 use anyhow::{Result, anyhow};
 
-mod utils;
-mod decoding;
-mod dicom_table;
 mod ui;
 
-use utils::{Format, RawImage, convert_to_BGRA8888};
-use decoding::get_image;
-use dicom_table::{TableEntry, get_dicom_table};
+use oxydicom::{
+    Format, Overlay, RawImage, TableEntry, convert_to_BGRA8888, draw_overlays,
+    get_dicom_table, get_display_frames, get_overlays, open_dicom
+};
 
 pub fn main() -> Result<()> {
 
@@ -29,14 +26,34 @@ pub fn main() -> Result<()> {
 
     let filepath = input_path.as_os_str().to_str().unwrap().to_owned();
 
-    let dicom = open_file(input_path.as_os_str()).unwrap();
+    let dicom = open_dicom(&input_path)?;
     let table = get_dicom_table(&dicom);
-    let image = get_image(&dicom).unwrap();
+    let frames = get_display_frames(&dicom)?;
+    // Malformed overlay groups are skipped, the image is still displayed
+    let overlays: Vec<Overlay> = get_overlays(&dicom)
+        .into_iter()
+        .filter_map(|overlay| overlay.map_err(|e| eprintln!("Warning: overlay skipped: {}", e)).ok())
+        .collect();
+
+    // Frames with the overlays drawn on top, none without overlays
+    let overlay_frames = match overlays.is_empty() {
+        true => Vec::new(),
+        false => frames.iter()
+            .enumerate()
+            .map(|(i, frame)| {
+                let mut overlay_frame = frame.clone();
+                draw_overlays(&mut overlay_frame, &overlays, i)?;
+                Ok(overlay_frame)
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
 
-    let Format { w, h, .. } = image.format;
+    let Format { w, h, .. } = frames[0].format;
 
+    // Frames that cannot be shown are reported like the other loading errors
     let flags = Flags { 
-        image,
+        image_handles: make_image_handles(&frames)?,
+        overlay_handles: make_image_handles(&overlay_frames)?,
         table,
         filepath
     };
@@ -57,7 +74,10 @@ pub fn main() -> Result<()> {
 }
 
 struct App {
-    image_handle: Handle,
+    image_handles: Vec<Handle>,
+    overlay_handles: Vec<Handle>,
+    show_overlays: bool,
+    frame_index: usize,
     filepath: String,
     table: Vec<TableEntry>,
     show_tags: bool,
@@ -68,18 +88,25 @@ struct App {
 struct States {
     scroll: scrollable::State,
     show_tags_button: button::State,
+    show_overlays_button: button::State,
+    previous_frame_button: button::State,
+    next_frame_button: button::State,
     table_buttons: Vec<[button::State; 3]>
 }
 
 struct Flags {
     filepath: String,
-    image: RawImage,
+    image_handles: Vec<Handle>,
+    overlay_handles: Vec<Handle>,
     table: Vec<TableEntry>
 }
 
 #[derive(Debug, Clone)]
 enum Message {
     TagsTogglePressed,
+    OverlaysTogglePressed,
+    PreviousFrame,
+    NextFrame,
     TableCellPressed(Option<String>)
 }
 
@@ -90,13 +117,7 @@ impl Application for App {
 
     fn new(flags: Flags) -> (Self, Command<Self::Message>) {
 
-        let Flags { filepath, image, table } = flags;
-
-        let image_data_bgra = convert_to_BGRA8888(&image).unwrap();
-
-        let RawImage { format, bytes } = image_data_bgra;
-        let Format { h, w, .. } = format;
-        let image_handle = Handle::from_pixels(w, h, bytes);
+        let Flags { filepath, image_handles, overlay_handles, table } = flags;
 
         let table_buttons_states = table
             .iter().map(|_| [
@@ -109,11 +130,17 @@ impl Application for App {
         let states = States {
             scroll: scrollable::State::new(),
             show_tags_button: button::State::new(),
+            show_overlays_button: button::State::new(),
+            previous_frame_button: button::State::new(),
+            next_frame_button: button::State::new(),
             table_buttons: table_buttons_states
         };
 
         let app = App { 
-            image_handle,
+            image_handles,
+            overlay_handles,
+            show_overlays: true,
+            frame_index: 0,
             filepath,
             table,
             show_tags: false,
@@ -131,6 +158,9 @@ impl Application for App {
     fn update(&mut self, message: Message) -> Command<Self::Message> {
         match message {
             Message::TagsTogglePressed => self.show_tags = !self.show_tags,
+            Message::OverlaysTogglePressed => self.show_overlays = !self.show_overlays,
+            Message::PreviousFrame => self.frame_index = self.frame_index.saturating_sub(1),
+            Message::NextFrame => self.frame_index = (self.frame_index + 1).min(self.image_handles.len() - 1),
             Message::TableCellPressed(Some(txt)) => self.clipoard.set_contents(txt).unwrap(),
             Message::TableCellPressed(None) => ()
         }
@@ -140,15 +170,36 @@ impl Application for App {
 
     fn view(&mut self) -> Element<Message> {
 
-        let image = Image::new(self.image_handle.clone());
+        let image_handles = match self.show_overlays && !self.overlay_handles.is_empty() {
+            true => &self.overlay_handles,
+            false => &self.image_handles
+        };
+
+        let image = Image::new(image_handles[self.frame_index].clone());
 
         let States {
             show_tags_button,
+            show_overlays_button,
+            previous_frame_button,
+            next_frame_button,
             table_buttons,
             scroll
         } = &mut self.states;
 
-        let header = make_header(&self.filepath, show_tags_button);
+        let mut header = make_header(&self.filepath, show_tags_button);
+
+        if !self.overlay_handles.is_empty() {
+            header = header.push(make_overlays_toggle(self.show_overlays, show_overlays_button));
+        }
+
+        if self.image_handles.len() > 1 {
+            header = header.push(make_frame_controls(
+                self.frame_index,
+                self.image_handles.len(),
+                previous_frame_button,
+                next_frame_button
+            ));
+        }
 
         let content: Element<Message> = if self.show_tags {
 
@@ -174,6 +225,19 @@ impl Application for App {
 }
 
 
+fn make_image_handles(frames: &[RawImage]) -> Result<Vec<Handle>> {
+    frames
+        .iter()
+        .map(|image| {
+            let image_data_bgra = convert_to_BGRA8888(image)?;
+            let RawImage { format, bytes } = image_data_bgra;
+            let Format { h, w, .. } = format;
+            Ok(Handle::from_pixels(w, h, bytes))
+        })
+        .collect()
+}
+
+
 fn make_tags_content<'a>(
     table: &Vec<TableEntry>,
     table_buttons: &'a mut Vec<[button::State; 3]>,
@@ -274,3 +338,47 @@ fn make_header<'a>(filepath: &String, button_state: &'a mut button::State) -> Ro
     )
     .padding(20)
 }
+
+
+fn make_overlays_toggle<'a>(show_overlays: bool, button_state: &'a mut button::State) -> Button<'a, Message> {
+
+    let label = match show_overlays {
+        true => "Hide overlays",
+        false => "Show overlays"
+    };
+
+    Button::new(button_state, Text::new(label).color(Color::WHITE))
+        .on_press(Message::OverlaysTogglePressed)
+        .style(ui::TagsButtonStyleSheet)
+}
+
+
+fn make_frame_controls<'a>(
+    frame_index: usize,
+    nb_frames: usize,
+    previous_button_state: &'a mut button::State,
+    next_button_state: &'a mut button::State
+) -> Row<'a, Message> {
+
+    Row::new()
+    .push(
+        Button::new(previous_button_state, Text::new("<").color(Color::WHITE))
+            .on_press(Message::PreviousFrame)
+            .style(ui::TagsButtonStyleSheet)
+    )
+    .push(
+        Container::new(
+            Text::new(format!("{} / {}", frame_index + 1, nb_frames))
+                .horizontal_alignment(HorizontalAlignment::Center)
+                .vertical_alignment(VerticalAlignment::Center)
+                .color(Color::WHITE)
+        )
+        .padding(5)
+    )
+    .push(
+        Button::new(next_button_state, Text::new(">").color(Color::WHITE))
+            .on_press(Message::NextFrame)
+            .style(ui::TagsButtonStyleSheet)
+    )
+    .spacing(5)
+}
@@ -0,0 +1,185 @@
+use std::cell::OnceCell;
+use std::convert::TryInto;
+use dicom::object::Tag;
+use dicom::core::value::PrimitiveValue;
+
+use crate::utils::{Dicom, RawImage};
+use crate::decoding::{DecodeError, Result, get_allocated_frames, get_int, get_multi_int, get_opt_int, get_str};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverlayType {
+    Graphics,
+    ROI
+}
+
+/// Overlay plane of a 60xx group, with one bitmap per overlay frame
+pub struct Overlay {
+    pub group: u16,
+    pub rows: u32,
+    pub columns: u32,
+    /// Row and column of the top left overlay pixel in the image, from 0
+    pub origin: (i64, i64),
+    pub overlay_type: OverlayType,
+    /// Image frame of the first overlay frame, from 0
+    pub first_frame: usize,
+    pub bitmaps: Vec<Vec<bool>>
+}
+
+/// Reads the overlay planes of the groups 6000 to 601E, one result per group.
+/// A malformed group does not prevent reading the others.
+pub fn get_overlays(dicom: &Dicom) -> Vec<Result<Overlay>> {
+
+    // Pixel data of the embedded overlays, decoded once for all groups
+    let allocated_frames = OnceCell::new();
+
+    (0x6000..=0x601E)
+        .step_by(2)
+        .filter(|group| dicom.element(Tag(*group, 0x0010)).is_ok())
+        .map(|group| get_overlay(dicom, group, &allocated_frames))
+        .collect()
+}
+
+fn get_overlay(dicom: &Dicom, group: u16, allocated_frames: &OnceCell<Result<Vec<RawImage>>>) -> Result<Overlay> {
+
+    /*
+        Overlay attributes
+    */
+
+    let rows: u32 = get_int(dicom, Tag(group, 0x0010))?;
+    let columns: u32 = get_int(dicom, Tag(group, 0x0011))?;
+
+    let overlay_type = match get_str(dicom, Tag(group, 0x0040))?.as_deref() {
+        Some("R") => OverlayType::ROI,
+        _ => OverlayType::Graphics
+    };
+
+    // Overlay Origin is 1-based and may be negative
+    let origin = match get_multi_int(dicom, Tag(group, 0x0050))?.as_deref() {
+        Some([row, column]) => (row - 1, column - 1),
+        Some(origin) => return Err(DecodeError::invalid_attribute(
+            Tag(group, 0x0050), format!("bad overlay origin {:?}", origin)
+        )),
+        None => (0, 0)
+    };
+
+    let nb_frames: usize = get_opt_int(dicom, Tag(group, 0x0015))?.unwrap_or(1);
+    let first_frame: usize = get_opt_int::<usize>(dicom, Tag(group, 0x0051))?.unwrap_or(1).saturating_sub(1);
+    let bits_allocated: Option<u32> = get_opt_int(dicom, Tag(group, 0x0100))?;
+    let bit_position: u32 = get_opt_int(dicom, Tag(group, 0x0102))?.unwrap_or(0);
+
+    let frame_len: usize = (u64::from(rows) * u64::from(columns)).try_into()?;
+
+    /*
+        Bitmaps
+    */
+
+    let bitmaps = match dicom.element(Tag(group, 0x3000)) {
+
+        // Bits are packed from the least significant bit, without gaps between frames
+        Ok(elem) => {
+            let bytes: Vec<u8> = match elem.value().primitive() {
+                Some(PrimitiveValue::U8(bytes)) => bytes.to_vec(),
+                Some(PrimitiveValue::U16(words)) => words.iter().flat_map(|w| w.to_le_bytes().to_vec()).collect(),
+                val => return Err(DecodeError::invalid_attribute(
+                    Tag(group, 0x3000), format!("unexpected type {:?}", val)
+                ))
+            };
+
+            let enough_bytes = frame_len.checked_mul(nb_frames).is_some_and(|nb_bits| bytes.len() * 8 >= nb_bits);
+            if !enough_bytes {
+                return Err(DecodeError::dimensions(format!(
+                    "Overlay {:04X}: {} bytes of data for {} frames of {}x{}", group, bytes.len(), nb_frames, columns, rows
+                )))
+            }
+
+            (0..nb_frames)
+                .map(|f| (f * frame_len..(f + 1) * frame_len).map(|i| bytes[i / 8] >> (i % 8) & 1 == 1).collect())
+                .collect()
+        },
+
+        // Retired overlays embedded in the unused high bits of the pixel data
+        Err(_) => {
+            let frames = allocated_frames
+                .get_or_init(|| get_allocated_frames(dicom))
+                .as_ref()
+                .map_err(|e| DecodeError::unsupported(format!("Overlay {:04X}: {}", group, e)))?;
+            let format = &frames.first()
+                .ok_or_else(|| DecodeError::dimensions(format!("Overlay {:04X}: no pixel data", group)))?
+                .format;
+
+            // Same as the pixel data when embedded
+            let bits_allocated = bits_allocated.unwrap_or(8 * format.channel_depth);
+
+            if bits_allocated != 8 * format.channel_depth || bit_position >= bits_allocated {
+                return Err(DecodeError::unsupported(format!(
+                    "Overlay {:04X}: embedded in bit {} of {} bits allocated", group, bit_position, bits_allocated
+                )))
+            }
+            if (columns, rows, format.channels) != (format.w, format.h, 1) {
+                return Err(DecodeError::dimensions(format!(
+                    "Overlay {:04X}: {}x{} embedded in a {}x{} image", group, columns, rows, format.w, format.h
+                )))
+            }
+
+            frames.iter()
+                .skip(first_frame)
+                .take(nb_frames)
+                .map(|frame| embedded_bitmap(frame, bit_position))
+                .collect()
+        }
+    };
+
+    Ok(Overlay { group, rows, columns, origin, overlay_type, first_frame, bitmaps })
+}
+
+fn embedded_bitmap(frame: &RawImage, bit_position: u32) -> Vec<bool> {
+    match frame.format.channel_depth {
+        1 => frame.bytes.iter().map(|v| v >> bit_position & 1 == 1).collect(),
+        _ => frame.bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) >> bit_position & 1 == 1)
+            .collect()
+    }
+}
+
+/// Draws the overlay pixels of an image frame in white on an 8-bit image
+pub fn draw_overlays(image: &mut RawImage, overlays: &[Overlay], frame_index: usize) -> Result<()> {
+
+    let RawImage { format, bytes } = image;
+
+    if format.channel_depth != 1 {
+        return Err(DecodeError::unsupported(format!(
+            "Overlays: unsupported bit depth: {} (1 supported)", format.channel_depth
+        )))
+    }
+
+    let channels: usize = format.channels.try_into()?;
+    let (w, h) = (i64::from(format.w), i64::from(format.h));
+
+    for overlay in overlays.iter() {
+
+        let bitmap = match frame_index.checked_sub(overlay.first_frame) {
+            Some(i) => match overlay.bitmaps.get(i) {
+                Some(bitmap) => bitmap,
+                None => continue
+            },
+            None => continue
+        };
+
+        let columns = i64::from(overlay.columns);
+
+        for (i, _) in bitmap.iter().enumerate().filter(|(_, set)| **set) {
+
+            let row = overlay.origin.0 + i as i64 / columns;
+            let column = overlay.origin.1 + i as i64 % columns;
+
+            if (0..h).contains(&row) && (0..w).contains(&column) {
+                let pixel = (row * w + column) as usize * channels;
+                bytes[pixel..pixel + channels].iter_mut().for_each(|v| *v = 255);
+            }
+        }
+    }
+
+    Ok(())
+}
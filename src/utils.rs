@@ -1,47 +1,88 @@
-use std::convert::TryInto;
 use std::path::Path;
+use std::sync::Arc;
 use dicom::object::mem::InMemDicomObject;
 use dicom::object::{RootDicomObject, StandardDataDictionary};
-use anyhow::{Result, anyhow};
+use anyhow::anyhow;
 
+use crate::decoding::{DecodeError, Lut, PixelCodec, Result};
 
-pub type Dicom = RootDicomObject<InMemDicomObject<StandardDataDictionary>>;
+
+pub type Dicom = RootDicomObject<DicomItem>;
+pub type DicomItem = InMemDicomObject<StandardDataDictionary>;
 
 #[derive(Debug, Clone)]
 pub enum Encoding {
     RAW,
-    RLE,
-    JPEG,
-    JPEG2000
+    RAWBigEndian,
+    /// Encapsulated frames, decoded by the codec registered for the transfer syntax
+    Encapsulated(Arc<dyn PixelCodec>)
 }
 
-pub type Palettes = Vec<Vec<u16>>;
+/// Red, green and blue Palette Color LUTs
+pub type Palettes = Vec<Lut>;
+
+/// Color space of 3-channel samples, from the photometric interpretation.
+/// RGB for anything else.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    RGB,
+    YBRFull,
+    YBRFull422,
+    YBRPartial422,
+    YBRICT,
+    YBRRCT
+}
 
 #[derive(Clone)]
 pub struct Format {
     pub w: u32,
     pub h: u32,
     pub channels: u32,
-    pub channel_depth: u32
+    pub channel_depth: u32,
+    pub signed: bool,
+    /// IEEE 754 samples of 4 or 8 bytes
+    pub float: bool
 }
 
 pub struct EncodedImage {
     pub target_format: Format,
     pub encoding: Encoding,
     pub palettes: Option<Palettes>,
-    pub bytes: Vec<u8>
+    pub color_space: ColorSpace,
+    pub planar: bool,
+    pub bits_stored: u32,
+    pub high_bit: u32,
+    pub frames: Vec<Vec<u8>>
 }
 
+#[derive(Clone)]
 pub struct RawImage {
     pub format: Format,
     pub bytes: Vec<u8>
 }
 
-pub fn write_image(image: &RawImage, path: &Path) -> Result<()> {
+/// Output of the modality LUT: physical values such as Hounsfield units
+pub struct ModalityImage {
+    pub w: u32,
+    pub h: u32,
+    pub channels: u32,
+    pub values: Vec<f32>
+}
+
+pub fn write_image(image: &RawImage, path: &Path) -> anyhow::Result<()> {
 
     let RawImage { format, bytes } = image;
 
-    let file = std::fs::File::create(path).unwrap();
+    // PNG has no float samples, they are written as 8-bit over their range
+    if format.float {
+        let image = RawImage {
+            format: Format { channel_depth: 1, signed: false, float: false, ..format.clone() },
+            bytes: auto_range_to_8bit(format, bytes)?
+        };
+        return write_image(&image, path)
+    }
+
+    let file = std::fs::File::create(path)?;
     let ref mut file_buf = std::io::BufWriter::new(file);
     
     let mut encoder = png::Encoder::new(file_buf, format.w, format.h);
@@ -52,9 +93,19 @@ pub fn write_image(image: &RawImage, path: &Path) -> Result<()> {
         _ => return Err(anyhow!("Unsupported color type"))
     };
 
-    let bit_depth = match format.channel_depth {
-        1 => png::BitDepth::Eight,
-        2 => png::BitDepth::Sixteen,
+    // PNG samples are unsigned, signed samples are offset by flipping their sign bit
+    let sign_bit = match format.signed {
+        true => 0x80,
+        false => 0
+    };
+
+    // PNG stores 16-bit samples in big endian order
+    let (bit_depth, png_bytes): (_, Vec<u8>) = match format.channel_depth {
+        1 => (png::BitDepth::Eight, bytes.iter().map(|v| v ^ sign_bit).collect()),
+        2 => (png::BitDepth::Sixteen, bytes
+                .chunks_exact(2)
+                .flat_map(|chunk| vec![chunk[1] ^ sign_bit, chunk[0]])
+                .collect()),
         _ => return Err(anyhow!("Unsupported bit depth"))
     };
 
@@ -62,9 +113,9 @@ pub fn write_image(image: &RawImage, path: &Path) -> Result<()> {
     encoder.set_color(color);
     encoder.set_depth(bit_depth);
 
-    let mut writer = encoder.write_header().unwrap();
+    let mut writer = encoder.write_header()?;
     
-    writer.write_image_data(bytes).unwrap();
+    writer.write_image_data(&png_bytes)?;
 
     Ok(())
 }
@@ -73,39 +124,45 @@ pub fn write_image(image: &RawImage, path: &Path) -> Result<()> {
 pub fn convert_to_BGRA8888(image: &RawImage) -> Result<RawImage> {
 
     let RawImage { format, bytes } = image;
-    let Format { channels, channel_depth, .. } = format;
+    let Format { channels, channel_depth, signed, float, .. } = format;
+
+    // Signed samples are shifted to the unsigned range by flipping their sign bit
+    let u8_bytes: Vec<u8> = match (channel_depth, signed) {
+
+        _ if *float => auto_range_to_8bit(format, bytes)?,
 
-    let u8_bytes: Vec<u8> = match channel_depth {
+        (1, false) => bytes.clone(),
 
-        1 => bytes.clone(),
+        (1, true) => bytes.iter().map(|v| v ^ 0x80).collect(),
 
-        2 => {
+        (2, _) => {
+
+            let sign_bit = match signed {
+                true => 0x8000,
+                false => 0
+            };
 
             bytes
                 .chunks_exact(2)
                 .map(|chunk| -> u8 {
-                    let val: u16 = u16::from_le_bytes(chunk.try_into().unwrap())
-                        .try_into().unwrap();
-                    (val >> 8).try_into().unwrap()
+                    let val: u16 = u16::from_le_bytes([chunk[0], chunk[1]]) ^ sign_bit;
+                    (val >> 8) as u8
                 })
                 .collect()
 
         },
 
-        _ => return Err(anyhow!(
+        _ => return Err(DecodeError::unsupported(format!(
             "RGBA conversion: unsupported format: {} channels of depth {} bytes",
             *channels, *channel_depth
-        ))
+        )))
     };
 
     let rgba_bytes: Vec<u8> = match channels  {
 
         3 => u8_bytes
                 .chunks_exact(3)
-                .flat_map(|chunk| {
-                    let [r, g, b]: [u8; 3] = chunk.try_into().unwrap();
-                    vec![b, g, r, 255]
-                })
+                .flat_map(|chunk| vec![chunk[2], chunk[1], chunk[0], 255])
                 .collect(),
 
         1 => u8_bytes
@@ -113,15 +170,59 @@ pub fn convert_to_BGRA8888(image: &RawImage) -> Result<RawImage> {
                 .flat_map(|v| vec![*v, *v, *v, 255])
                 .collect(),  
                 
-        _ => return Err(anyhow!(
+        _ => return Err(DecodeError::unsupported(format!(
             "RGBA conversion: unsupported format: {} channels of depth {} bytes",
             *channels, *channel_depth
-        ))
+        )))
     };
 
     let mut new_format = format.clone();
     new_format.channels = 3;
     new_format.channel_depth = 1;
+    new_format.signed = false;
+    new_format.float = false;
 
     Ok(RawImage { bytes: rgba_bytes, format: new_format } )
 }
+
+/// Little endian f32 or f64 samples
+pub fn float_samples(format: &Format, bytes: &[u8]) -> Result<Vec<f64>> {
+
+    let samples = match format.channel_depth {
+        4 => bytes.chunks_exact(4).map(|b| f64::from(f32::from_le_bytes([b[0], b[1], b[2], b[3]]))).collect(),
+        8 => bytes.chunks_exact(8).map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])).collect(),
+        _ => return Err(DecodeError::unsupported(format!(
+            "Float samples: unsupported bit depth: {} (4 and 8 supported)",
+            format.channel_depth
+        )))
+    };
+
+    Ok(samples)
+}
+
+/// Maps the range of float samples to 0-255, NaN and infinite samples are ignored
+/// when computing the range and mapped to 0 or 255.
+pub fn auto_range_to_8bit(format: &Format, bytes: &[u8]) -> Result<Vec<u8>> {
+
+    let samples = float_samples(format, bytes)?;
+
+    let (min, max) = samples
+        .iter()
+        .filter(|v| v.is_finite())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &v| (min.min(v), max.max(v)));
+
+    let scale = match max > min {
+        true => 255.0 / (max - min),
+        false => 0.0
+    };
+
+    let u8_bytes = samples
+        .iter()
+        .map(|v| match v.is_nan() {
+            true => 0,
+            false => ((v - min) * scale).round().clamp(0.0, 255.0) as u8
+        })
+        .collect();
+
+    Ok(u8_bytes)
+}
@@ -0,0 +1,1606 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::fs::File;
+
+const IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+const EXPLICIT_VR_LE: &str = "1.2.840.10008.1.2.1";
+const EXPLICIT_VR_BE: &str = "1.2.840.10008.1.2.2";
+const DEFLATED_EXPLICIT_VR_LE: &str = "1.2.840.10008.1.2.1.99";
+const RLE_LOSSLESS: &str = "1.2.840.10008.1.2.5";
+
+
+/*
+    Synthetic DICOM files
+*/
+
+#[derive(Clone)]
+struct SyntheticDicom {
+    transfer_syntax: &'static str,
+    elements: Vec<(u16, u16, &'static str, Vec<u8>)>,
+    pixel_sequence: Option<(Vec<u8>, Vec<Vec<u8>>)>
+}
+
+impl SyntheticDicom {
+
+    fn new(transfer_syntax: &'static str) -> Self {
+        SyntheticDicom { transfer_syntax, elements: Vec::new(), pixel_sequence: None }
+    }
+
+    fn image(transfer_syntax: &'static str, w: u16, h: u16, samples: u16, bits: u16) -> Self {
+
+        let photo_interp = match samples {
+            3 => "RGB",
+            _ => "MONOCHROME2"
+        };
+
+        Self::new(transfer_syntax)
+            .us(0x0028, 0x0002, samples)
+            .cs(0x0028, 0x0004, photo_interp)
+            .us(0x0028, 0x0010, h)
+            .us(0x0028, 0x0011, w)
+            .us(0x0028, 0x0100, bits)
+            .us(0x0028, 0x0101, bits)
+            .us(0x0028, 0x0102, bits - 1)
+            .us(0x0028, 0x0103, 0)
+    }
+
+    fn element(mut self, group: u16, elem: u16, vr: &'static str, mut value: Vec<u8>) -> Self {
+        if value.len() % 2 == 1 {
+            value.push(if vr == "OB" { 0 } else { b' ' });
+        }
+        self.elements.retain(|(g, e, ..)| (*g, *e) != (group, elem));
+        self.elements.push((group, elem, vr, value));
+        self
+    }
+
+    fn big_endian(&self) -> bool {
+        self.transfer_syntax == EXPLICIT_VR_BE
+    }
+
+    fn us(self, group: u16, elem: u16, value: u16) -> Self {
+        let bytes = match self.big_endian() {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes()
+        };
+        self.element(group, elem, "US", bytes.to_vec())
+    }
+
+    /// Multi-valued US, or OW data
+    fn words(self, group: u16, elem: u16, vr: &'static str, values: &[u16]) -> Self {
+        let bytes = values.iter()
+            .flat_map(|v| match self.big_endian() {
+                true => v.to_be_bytes(),
+                false => v.to_le_bytes()
+            }.to_vec())
+            .collect();
+        self.element(group, elem, vr, bytes)
+    }
+
+    fn cs(self, group: u16, elem: u16, value: &str) -> Self {
+        self.element(group, elem, "CS", value.as_bytes().to_vec())
+    }
+
+    fn bits_stored(self, bits_stored: u16, high_bit: u16) -> Self {
+        self.us(0x0028, 0x0101, bits_stored)
+            .us(0x0028, 0x0102, high_bit)
+    }
+
+    fn without(mut self, group: u16, elem: u16) -> Self {
+        self.elements.retain(|(g, e, ..)| (*g, *e) != (group, elem));
+        self
+    }
+
+    fn pixel_data(self, vr: &'static str, bytes: Vec<u8>) -> Self {
+        self.element(0x7FE0, 0x0010, vr, bytes)
+    }
+
+    fn is(self, group: u16, elem: u16, value: u32) -> Self {
+        self.element(group, elem, "IS", value.to_string().into_bytes())
+    }
+
+    fn ds(self, group: u16, elem: u16, value: &str) -> Self {
+        self.element(group, elem, "DS", value.as_bytes().to_vec())
+    }
+
+    /// Encapsulated pixel data, written after the other elements
+    fn pixel_sequence(mut self, offset_table: Vec<u8>, fragments: Vec<Vec<u8>>) -> Self {
+        self.pixel_sequence = Some((offset_table, fragments));
+        self
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+
+        let mut meta = Vec::new();
+        meta.append(&mut explicit_element(0x0002, 0x0001, "OB", &[0, 1]));
+        meta.append(&mut explicit_element(0x0002, 0x0002, "UI", &uid_bytes("1.2.840.10008.5.1.4.1.1.7")));
+        meta.append(&mut explicit_element(0x0002, 0x0003, "UI", &uid_bytes("1.2.3.4")));
+        meta.append(&mut explicit_element(0x0002, 0x0010, "UI", &uid_bytes(self.transfer_syntax)));
+        meta.append(&mut explicit_element(0x0002, 0x0012, "UI", &uid_bytes("1.2.3.4.5")));
+
+        let mut bytes = vec![0; 128];
+        bytes.extend_from_slice(b"DICM");
+        bytes.append(&mut explicit_element(0x0002, 0x0000, "UL", &(meta.len() as u32).to_le_bytes()));
+        bytes.append(&mut meta);
+
+        let mut dataset = Vec::new();
+        for (group, elem, vr, value) in self.elements.iter() {
+            let mut encoded = match self.transfer_syntax {
+                IMPLICIT_VR_LE => implicit_element(*group, *elem, value),
+                EXPLICIT_VR_BE => explicit_element_be(*group, *elem, vr, value),
+                _ => explicit_element(*group, *elem, vr, value)
+            };
+            dataset.append(&mut encoded);
+        }
+
+        if let Some((offset_table, fragments)) = &self.pixel_sequence {
+            dataset.extend_from_slice(&[0xE0, 0x7F, 0x10, 0x00]);
+            dataset.extend_from_slice(b"OB\0\0");
+            dataset.extend_from_slice(&0xFFFF_FFFFu32.to_le_bytes());
+            dataset.append(&mut item(0xE000, offset_table));
+            for fragment in fragments.iter() {
+                dataset.append(&mut item(0xE000, fragment));
+            }
+            dataset.append(&mut item(0xE0DD, &[]));
+        }
+
+        if self.transfer_syntax == DEFLATED_EXPLICIT_VR_LE {
+            dataset = miniz_oxide::deflate::compress_to_vec(&dataset, 6);
+        }
+
+        bytes.append(&mut dataset);
+        bytes
+    }
+}
+
+fn uid_bytes(uid: &str) -> Vec<u8> {
+    let mut bytes = uid.as_bytes().to_vec();
+    if bytes.len() % 2 == 1 {
+        bytes.push(0);
+    }
+    bytes
+}
+
+fn item(elem: u16, value: &[u8]) -> Vec<u8> {
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&0xFFFEu16.to_le_bytes());
+    bytes.extend_from_slice(&elem.to_le_bytes());
+    bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(value);
+    bytes
+}
+
+/// RLE frame with one literal run per segment
+fn rle_frame(segments: &[Vec<u8>]) -> Vec<u8> {
+
+    let mut bytes = vec![0; 64];
+    bytes[..4].copy_from_slice(&(segments.len() as u32).to_le_bytes());
+
+    for (i, segment) in segments.iter().enumerate() {
+        let offset = bytes.len() as u32;
+        bytes[4 + i * 4..8 + i * 4].copy_from_slice(&offset.to_le_bytes());
+        bytes.push((segment.len() - 1) as u8);
+        bytes.extend_from_slice(segment);
+        if bytes.len() % 2 == 1 {
+            bytes.push(0);
+        }
+    }
+
+    bytes
+}
+
+fn explicit_element(group: u16, elem: u16, vr: &str, value: &[u8]) -> Vec<u8> {
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&group.to_le_bytes());
+    bytes.extend_from_slice(&elem.to_le_bytes());
+    bytes.extend_from_slice(vr.as_bytes());
+
+    match vr {
+        "OB" | "OW" | "OF" | "OD" | "OL" | "OV" | "SQ" | "UN" | "UT" => {
+            bytes.extend_from_slice(&[0, 0]);
+            bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        },
+        _ => bytes.extend_from_slice(&(value.len() as u16).to_le_bytes())
+    }
+
+    bytes.extend_from_slice(value);
+    bytes
+}
+
+fn explicit_element_be(group: u16, elem: u16, vr: &str, value: &[u8]) -> Vec<u8> {
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&group.to_be_bytes());
+    bytes.extend_from_slice(&elem.to_be_bytes());
+    bytes.extend_from_slice(vr.as_bytes());
+
+    match vr {
+        "OB" | "OW" | "OF" | "OD" | "OL" | "OV" | "SQ" | "UN" | "UT" => {
+            bytes.extend_from_slice(&[0, 0]);
+            bytes.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        },
+        _ => bytes.extend_from_slice(&(value.len() as u16).to_be_bytes())
+    }
+
+    bytes.extend_from_slice(value);
+    bytes
+}
+
+fn implicit_element(group: u16, elem: u16, value: &[u8]) -> Vec<u8> {
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&group.to_le_bytes());
+    bytes.extend_from_slice(&elem.to_le_bytes());
+    bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(value);
+    bytes
+}
+
+
+/*
+    Synthetic codestreams
+*/
+
+/// Entropy-coded segment, most significant bit first with 0xFF bytes stuffed
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u8,
+    n: u32
+}
+
+impl BitWriter {
+
+    fn put(&mut self, value: u32, n: u32) {
+        for i in (0..n).rev() {
+            self.acc = (self.acc << 1) | ((value >> i) & 1) as u8;
+            self.n += 1;
+            if self.n == 8 {
+                self.bytes.push(self.acc);
+                if self.acc == 0xFF {
+                    self.bytes.push(0);
+                }
+                self.acc = 0;
+                self.n = 0;
+            }
+        }
+    }
+
+    /// Pads the last byte with 1 bits
+    fn finish(mut self) -> Vec<u8> {
+        if self.n > 0 {
+            let pad = 8 - self.n;
+            self.put((1 << pad) - 1, pad);
+        }
+        self.bytes
+    }
+}
+
+fn marker_segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0xFF, marker];
+    bytes.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// Sequential DCT codestream of one component (SOF1) made of 8x8 blocks holding only
+/// a DC coefficient: every sample of a block is 2^(precision - 1) + DC * quantization / 8.
+/// DC difference categories are coded on 5 bits, the only AC code is the end of block.
+fn dc_only_jpeg(precision: u8, w: u16, h: u16, quantization: u16, dc_differences: &[i32]) -> Vec<u8> {
+
+    let wide = quantization > 255;
+    let mut dqt = vec![if wide { 0x10 } else { 0x00 }];
+    for _ in 0..64 {
+        match wide {
+            true => dqt.extend_from_slice(&quantization.to_be_bytes()),
+            false => dqt.push(quantization as u8)
+        }
+    }
+
+    let mut sof = vec![precision];
+    sof.extend_from_slice(&h.to_be_bytes());
+    sof.extend_from_slice(&w.to_be_bytes());
+    sof.extend_from_slice(&[1, 1, 0x11, 0]);
+
+    let mut dc_table = vec![0x00, 0, 0, 0, 0, 17, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    dc_table.extend(0..=16u8);
+    let ac_table = [0x10, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x00];
+
+    let mut bits = BitWriter::default();
+    for &diff in dc_differences {
+        let size = 32 - diff.unsigned_abs().leading_zeros();
+        let value = if diff < 0 { diff - 1 } else { diff };
+        bits.put(size, 5);
+        bits.put(value as u32 & ((1 << size) - 1), size);
+        bits.put(0, 1);
+    }
+
+    [
+        vec![0xFF, 0xD8],
+        marker_segment(0xDB, &dqt),
+        marker_segment(0xC1, &sof),
+        marker_segment(0xC4, &dc_table),
+        marker_segment(0xC4, &ac_table),
+        marker_segment(0xDA, &[1, 1, 0x00, 0, 63, 0]),
+        bits.finish(),
+        vec![0xFF, 0xD9]
+    ].concat()
+}
+
+
+/*
+    Codec fixtures
+*/
+
+/// Samples the codestreams of tests/fixtures were encoded from:
+/// noise on even rows, runs of 4 equal samples on odd rows.
+/// JPEG fixtures are encoded by libjpeg-turbo 3.1, JPEG-LS fixtures by CharLS 2.4
+/// and JPEG 2000 fixtures by OpenJPEG.
+fn fixture_samples(w: usize, h: usize, channels: usize, bits: u32) -> Vec<u16> {
+    (0..w * h * channels)
+        .map(|i| {
+            let (y, x) = (i / (w * channels), i % (w * channels) / channels);
+            match y % 2 {
+                0 => ((i as u32 + 1).wrapping_mul(2654435761) >> (32 - bits)) as u16,
+                _ => (((y * 5 + x / 4) % 64) << (bits - 6)) as u16
+            }
+        })
+        .collect()
+}
+
+fn fixture(name: &str) -> Vec<u8> {
+    std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)).unwrap()
+}
+
+fn fixture_dicom(transfer_syntax: &'static str, name: &str, w: u16, h: u16, channels: u16, bits: u16) -> SyntheticDicom {
+
+    let mut codestream = fixture(name);
+    if codestream.len() % 2 == 1 {
+        codestream.push(0);
+    }
+
+    SyntheticDicom::image(transfer_syntax, w, h, channels, if bits > 8 { 16 } else { 8 })
+        .bits_stored(bits, bits - 1)
+        .pixel_sequence(Vec::new(), vec![codestream])
+}
+
+/// Decodes a fixture stored with `transfer_syntax` to the samples of the PNG
+fn decode_fixture(transfer_syntax: &'static str, name: &str, w: u16, h: u16, channels: u16, bits: u16) -> Vec<u16> {
+    png_samples(&run_dicom2png(name, &fixture_dicom(transfer_syntax, name, w, h, channels, bits)).unwrap())
+}
+
+
+/*
+    Running dicom2png
+*/
+
+struct Png {
+    w: u32,
+    h: u32,
+    color_type: png::ColorType,
+    bit_depth: png::BitDepth,
+    bytes: Vec<u8>
+}
+
+fn run_dicom2png(name: &str, dicom: &SyntheticDicom) -> Result<Png, String> {
+    run_dicom2png_with_args(name, dicom, &[])
+}
+
+fn run_dicom2png_with_args(name: &str, dicom: &SyntheticDicom, args: &[&str]) -> Result<Png, String> {
+    run_dicom2png_on_bytes(name, &dicom.to_bytes(), args)
+}
+
+fn run_dicom2png_on_bytes(name: &str, bytes: &[u8], args: &[&str]) -> Result<Png, String> {
+
+    let dir = std::env::temp_dir().join("oxydicom-tests");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let input_path: PathBuf = dir.join(format!("{}.dcm", name));
+    let output_path: PathBuf = dir.join(format!("{}.png", name));
+    std::fs::write(&input_path, bytes).unwrap();
+    let _ = std::fs::remove_file(&output_path);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dicom2png"))
+        .arg(&input_path)
+        .arg(&output_path)
+        .args(args)
+        .output()
+        .unwrap();
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+    }
+
+    Ok(read_png(&output_path))
+}
+
+/// Multi-frame images are written to numbered files next to the output path
+fn run_dicom2png_frames(name: &str, dicom: &SyntheticDicom, nb_frames: usize) -> Result<Vec<Png>, String> {
+
+    let dir = std::env::temp_dir().join("oxydicom-tests");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let input_path: PathBuf = dir.join(format!("{}.dcm", name));
+    let output_path: PathBuf = dir.join(format!("{}.png", name));
+    std::fs::write(&input_path, dicom.to_bytes()).unwrap();
+
+    let frame_paths: Vec<PathBuf> = (1..=nb_frames)
+        .map(|i| dir.join(format!("{}_{}.png", name, i)))
+        .collect();
+    for path in frame_paths.iter() {
+        let _ = std::fs::remove_file(path);
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dicom2png"))
+        .arg(&input_path)
+        .arg(&output_path)
+        .output()
+        .unwrap();
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+    }
+
+    Ok(frame_paths.iter().map(|path| read_png(path)).collect())
+}
+
+fn png_samples(png: &Png) -> Vec<u16> {
+    match png.bit_depth {
+        png::BitDepth::Sixteen => png.bytes.chunks(2).map(|b| u16::from_be_bytes([b[0], b[1]])).collect(),
+        _ => png.bytes.iter().map(|&v| v as u16).collect()
+    }
+}
+
+fn read_png(path: &Path) -> Png {
+
+    let mut decoder = png::Decoder::new(File::open(path).unwrap());
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let (info, mut reader) = decoder.read_info().unwrap();
+    let mut bytes = vec![0; info.buffer_size()];
+    reader.next_frame(&mut bytes).unwrap();
+
+    Png {
+        w: info.width,
+        h: info.height,
+        color_type: info.color_type,
+        bit_depth: info.bit_depth,
+        bytes
+    }
+}
+
+
+/*
+    Tests
+*/
+
+#[test]
+fn implicit_vr_little_endian_grayscale_8bit() {
+
+    let pixels: Vec<u8> = (0..12).map(|i| i * 20).collect();
+    let dicom = SyntheticDicom::image(IMPLICIT_VR_LE, 4, 3, 1, 8)
+        .pixel_data("OW", pixels.clone());
+
+    let png = run_dicom2png("implicit_gray8", &dicom).unwrap();
+
+    assert_eq!((png.w, png.h), (4, 3));
+    assert_eq!(png.color_type, png::ColorType::Grayscale);
+    assert_eq!(png.bit_depth, png::BitDepth::Eight);
+    assert_eq!(png.bytes, pixels);
+}
+
+#[test]
+fn implicit_vr_little_endian_rgb_8bit() {
+
+    let pixels: Vec<u8> = (0..2*2*3).map(|i| 255 - i * 10).collect();
+    let dicom = SyntheticDicom::image(IMPLICIT_VR_LE, 2, 2, 3, 8)
+        .pixel_data("OW", pixels.clone());
+
+    let png = run_dicom2png("implicit_rgb8", &dicom).unwrap();
+
+    assert_eq!(png.color_type, png::ColorType::RGB);
+    assert_eq!(png.bytes, pixels);
+}
+
+#[test]
+fn implicit_and_explicit_vr_decode_identically() {
+
+    let pixels: Vec<u8> = (0..16).map(|i| i * 15).collect();
+
+    let implicit = SyntheticDicom::image(IMPLICIT_VR_LE, 4, 4, 1, 8)
+        .pixel_data("OW", pixels.clone());
+    let explicit = SyntheticDicom::image(EXPLICIT_VR_LE, 4, 4, 1, 8)
+        .pixel_data("OB", pixels.clone());
+
+    let implicit_png = run_dicom2png("implicit_vs_explicit_1", &implicit).unwrap();
+    let explicit_png = run_dicom2png("implicit_vs_explicit_2", &explicit).unwrap();
+
+    assert_eq!(implicit_png.bytes, explicit_png.bytes);
+
+    // Transfer syntax UID padded with a space instead of a null byte
+    let space_padded = SyntheticDicom { transfer_syntax: "1.2.840.10008.1.2.1 ", ..explicit };
+    assert_eq!(run_dicom2png("implicit_vs_explicit_3", &space_padded).unwrap().bytes, implicit_png.bytes);
+}
+
+#[test]
+fn explicit_vr_big_endian_grayscale_16bit() {
+
+    let values: Vec<u16> = vec![0, 1, 0x00FF, 0x0100, 0x1234, 0xABCD, 0xFF00, 0xFFFF];
+    let be_bytes: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect();
+
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_BE, 4, 2, 1, 16)
+        .pixel_data("OW", be_bytes.clone());
+
+    let png = run_dicom2png("big_endian_gray16", &dicom).unwrap();
+
+    // PNG stores 16-bit samples in big endian order as well
+    assert_eq!(png.bit_depth, png::BitDepth::Sixteen);
+    assert_eq!(png.bytes, be_bytes);
+}
+
+#[test]
+fn explicit_vr_big_endian_rgb_8bit() {
+
+    let pixels: Vec<u8> = (0..2*2*3).map(|i| i * 7).collect();
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_BE, 2, 2, 3, 8)
+        .pixel_data("OB", pixels.clone());
+
+    let png = run_dicom2png("big_endian_rgb8", &dicom).unwrap();
+
+    assert_eq!(png.bytes, pixels);
+}
+
+#[test]
+fn little_endian_grayscale_16bit_is_exported_in_png_byte_order() {
+
+    let values: Vec<u16> = vec![0x0102, 0x0304, 0xA0B0, 0xFFFE];
+    let le_bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+    let be_bytes: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect();
+
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 1, 16)
+        .pixel_data("OW", le_bytes);
+
+    let png = run_dicom2png("little_endian_gray16", &dicom).unwrap();
+
+    assert_eq!(png.bytes, be_bytes);
+}
+
+#[test]
+fn deflated_explicit_vr_little_endian() {
+
+    let pixels: Vec<u8> = (0..8*8).map(|i| (i * 3) as u8).collect();
+    let dicom = SyntheticDicom::image(DEFLATED_EXPLICIT_VR_LE, 8, 8, 1, 8)
+        .pixel_data("OB", pixels.clone());
+
+    let png = run_dicom2png("deflated_gray8", &dicom).unwrap();
+
+    assert_eq!((png.w, png.h), (8, 8));
+    assert_eq!(png.bytes, pixels);
+}
+
+#[test]
+fn native_multi_frame_is_split_by_frame_size() {
+
+    let frames: Vec<Vec<u8>> = (0..3u8)
+        .map(|f| (0..6u8).map(|i| f * 50 + i).collect())
+        .collect();
+
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 3, 2, 1, 8)
+        .is(0x0028, 0x0008, 3)
+        .pixel_data("OB", frames.concat());
+
+    let pngs = run_dicom2png_frames("native_multi_frame", &dicom, 3).unwrap();
+
+    for (png, frame) in pngs.iter().zip(frames.iter()) {
+        assert_eq!((png.w, png.h), (3, 2));
+        assert_eq!(&png.bytes, frame);
+    }
+}
+
+#[test]
+fn encapsulated_frames_follow_the_basic_offset_table() {
+
+    // Single segment RLE frames made of one literal run
+    let frames: Vec<Vec<u8>> = (0..2u8)
+        .map(|f| (0..8u8).map(|i| 200 - f * 100 + i).collect())
+        .collect();
+    let rle_frames: Vec<Vec<u8>> = frames.iter()
+        .map(|frame| {
+            let mut bytes = vec![0; 64];
+            bytes[0] = 1;
+            bytes[4] = 64;
+            bytes.push(7);
+            bytes.extend_from_slice(frame);
+            bytes.push(0);
+            bytes
+        })
+        .collect();
+
+    // The first frame spans two fragments
+    let fragments = vec![
+        rle_frames[0][..40].to_vec(),
+        rle_frames[0][40..].to_vec(),
+        rle_frames[1].clone()
+    ];
+    let second_frame_offset = (8 + fragments[0].len() + 8 + fragments[1].len()) as u32;
+    let offset_table = [0u32.to_le_bytes(), second_frame_offset.to_le_bytes()].concat();
+
+    let dicom = SyntheticDicom::image(RLE_LOSSLESS, 4, 2, 1, 8)
+        .is(0x0028, 0x0008, 2)
+        .pixel_sequence(offset_table, fragments);
+
+    let pngs = run_dicom2png_frames("encapsulated_multi_frame", &dicom, 2).unwrap();
+
+    assert_eq!(pngs[0].bytes, frames[0]);
+    assert_eq!(pngs[1].bytes, frames[1]);
+}
+
+#[test]
+fn extended_offset_table_is_found_by_walking_the_data_set() {
+
+    let ov_header = vec![0xE0, 0x7F, 0x01, 0x00, b'O', b'V', 0x00, 0x00];
+
+    let frames: Vec<Vec<u8>> = (0..2u8)
+        .map(|f| (0..8u8).map(|i| 50 + f * 100 + i).collect())
+        .collect();
+    let rle_frames: Vec<Vec<u8>> = frames.iter().map(|frame| rle_frame(std::slice::from_ref(frame))).collect();
+
+    let offsets = [0u64, (8 + rle_frames[0].len()) as u64];
+    let lengths = [rle_frames[0].len() as u64, rle_frames[1].len() as u64];
+    let to_bytes = |values: &[u64]| values.iter().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>();
+
+    // A private value holding the header of the Extended Offset Table comes first
+    let dicom = SyntheticDicom::image(RLE_LOSSLESS, 4, 2, 1, 8)
+        .is(0x0028, 0x0008, 2)
+        .element(0x0009, 0x1010, "OB", ov_header.clone())
+        .element(0x7FE0, 0x0001, "OV", to_bytes(&offsets))
+        .element(0x7FE0, 0x0002, "OV", to_bytes(&lengths))
+        .pixel_sequence(Vec::new(), rle_frames);
+
+    let pngs = run_dicom2png_frames("extended_offset_table", &dicom, 2).unwrap();
+
+    assert_eq!(pngs[0].bytes, frames[0]);
+    assert_eq!(pngs[1].bytes, frames[1]);
+
+    // Native pixel data holding the same bytes is left untouched
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 8, 1, 1, 8).pixel_data("OB", ov_header.clone());
+
+    assert_eq!(run_dicom2png("ov_header_in_pixel_data", &dicom).unwrap().bytes, ov_header);
+}
+
+#[test]
+fn single_frame_fragments_are_concatenated_without_offset_table() {
+
+    let pixels: Vec<u8> = (0..16u8).map(|i| i * 16).collect();
+
+    let mut rle = vec![0; 64];
+    rle[0] = 1;
+    rle[4] = 64;
+    rle.push(15);
+    rle.extend_from_slice(&pixels);
+    rle.push(0);
+
+    let dicom = SyntheticDicom::image(RLE_LOSSLESS, 4, 4, 1, 8)
+        .pixel_sequence(Vec::new(), vec![rle[..70].to_vec(), rle[70..].to_vec()]);
+
+    let png = run_dicom2png("split_single_frame", &dicom).unwrap();
+
+    assert_eq!(png.bytes, pixels);
+}
+
+#[test]
+fn one_bit_frames_are_unpacked_across_byte_boundaries() {
+
+    // Two 3x3 frames, the second one starts at bit 9
+    let bits: Vec<u8> = vec![1, 0, 0, 1, 1, 0, 1, 0, 1, 0, 1, 1, 0, 0, 0, 1, 1, 1];
+    let mut packed = vec![0u8; 4];
+    for (i, bit) in bits.iter().enumerate() {
+        packed[i / 8] |= bit << (i % 8);
+    }
+
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 3, 3, 1, 1)
+        .is(0x0028, 0x0008, 2)
+        .pixel_data("OB", packed);
+
+    let pngs = run_dicom2png_frames("one_bit", &dicom, 2).unwrap();
+
+    assert_eq!(pngs[0].bytes, bits[..9].to_vec());
+    assert_eq!(pngs[1].bytes, bits[9..].to_vec());
+}
+
+#[test]
+fn packed_12bit_samples_are_unpacked() {
+
+    // Two samples in three bytes: 0xABC and 0x123
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 2, 1, 1, 12)
+        .pixel_data("OW", vec![0xBC, 0x3A, 0x12]);
+
+    let png = run_dicom2png("packed_12bit", &dicom).unwrap();
+
+    assert_eq!(png.bit_depth, png::BitDepth::Sixteen);
+    assert_eq!(png.bytes, vec![0x0A, 0xBC, 0x01, 0x23]);
+}
+
+#[test]
+fn bits_outside_bits_stored_are_masked() {
+
+    // 12 bits stored with high bit 13: two garbage bits on each side
+    let values: Vec<u16> = vec![0x0FFF, 0x0123, 0x0000, 0x0ABC];
+    let words: Vec<u8> = values.iter()
+        .flat_map(|v| ((v << 2) | 0xC003).to_le_bytes().to_vec())
+        .collect();
+
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 1, 16)
+        .bits_stored(12, 13)
+        .pixel_data("OW", words);
+
+    let png = run_dicom2png("masked_bits", &dicom).unwrap();
+
+    let be_bytes: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect();
+    assert_eq!(png.bytes, be_bytes);
+}
+
+#[test]
+fn signed_samples_are_sign_extended_and_exported_as_offset_binary() {
+
+    let values: Vec<i16> = vec![-2048, -1, 0, 2047];
+    let stored: Vec<u8> = values.iter()
+        .flat_map(|v| ((*v as u16) & 0x0FFF).to_le_bytes().to_vec())
+        .collect();
+
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 1, 16)
+        .bits_stored(12, 11)
+        .us(0x0028, 0x0103, 1)
+        .pixel_data("OW", stored);
+
+    let png = run_dicom2png("signed_12bit", &dicom).unwrap();
+
+    let expected: Vec<u8> = values.iter()
+        .flat_map(|v| ((*v as u16) ^ 0x8000).to_be_bytes().to_vec())
+        .collect();
+    assert_eq!(png.bytes, expected);
+}
+
+#[test]
+fn display_output_applies_rescale_and_window() {
+
+    // CT values from -1024 to 1024 HU, windowed on [-160, 240]
+    let stored: Vec<u16> = vec![0, 864, 1024, 1064, 1264, 2048];
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 3, 2, 1, 16)
+        .ds(0x0028, 0x1050, "40")
+        .ds(0x0028, 0x1051, "400")
+        .ds(0x0028, 0x1052, "-1024")
+        .ds(0x0028, 0x1053, "1")
+        .cs(0x0028, 0x1056, "LINEAR_EXACT")
+        .pixel_data("OW", stored.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect());
+
+    let png = run_dicom2png_with_args("display_window", &dicom, &["--display"]).unwrap();
+
+    assert_eq!(png.bit_depth, png::BitDepth::Eight);
+    assert_eq!(png.bytes, vec![0, 0, 102, 128, 255, 255]);
+}
+
+/// Sequence of one LUT item, with its descriptor and 16-bit entries
+fn lut_sequence(first_mapped: u16, bits: u16, entries: &[u16]) -> Vec<u8> {
+
+    let descriptor: Vec<u8> = [entries.len() as u16, first_mapped, bits].iter().flat_map(|v| v.to_le_bytes()).collect();
+    let data: Vec<u8> = entries.iter().flat_map(|v| v.to_le_bytes()).collect();
+
+    let mut lut_item = explicit_element(0x0028, 0x3002, "US", &descriptor);
+    lut_item.append(&mut explicit_element(0x0028, 0x3006, "OW", &data));
+    item(0xE000, &lut_item)
+}
+
+#[test]
+fn display_output_uses_modality_lut_sequence() {
+
+    // Stored values below the first mapped value or past the last entry are clamped
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 3, 2, 1, 8)
+        .element(0x0028, 0x3000, "SQ", lut_sequence(2, 16, &[100, 300, 500, 700]))
+        .pixel_data("OB", vec![0, 2, 3, 4, 5, 200]);
+
+    let png = run_dicom2png_with_args("display_modality_lut", &dicom, &["--display"]).unwrap();
+
+    assert_eq!(png.bytes, vec![0, 0, 85, 170, 255, 255]);
+}
+
+#[test]
+fn display_output_uses_voi_lut_sequence() {
+
+    // LUT of 4 entries of 10 bits, starting at stored value 2
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 3, 2, 1, 8)
+        .element(0x0028, 0x3010, "SQ", lut_sequence(2, 10, &[0, 341, 682, 1023]))
+        .pixel_data("OB", vec![0, 2, 3, 4, 5, 200]);
+
+    let png = run_dicom2png_with_args("display_voi_lut", &dicom, &["--display"]).unwrap();
+
+    assert_eq!(png.bytes, vec![0, 0, 85, 170, 255, 255]);
+}
+
+#[test]
+fn display_output_without_voi_stretches_the_value_range() {
+
+    let values: Vec<u16> = vec![1000, 1100, 1200, 1300];
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 1, 16)
+        .pixel_data("OW", values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect());
+
+    let png = run_dicom2png_with_args("display_min_max", &dicom, &["--display"]).unwrap();
+
+    assert_eq!(png.bytes, vec![0, 85, 170, 255]);
+}
+
+#[test]
+fn display_output_inverts_monochrome1_and_inverse_presentation_shape() {
+
+    let pixels: Vec<u8> = vec![0, 85, 170, 255];
+    let inverted: Vec<u8> = vec![255, 170, 85, 0];
+
+    let monochrome1 = SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 1, 8)
+        .cs(0x0028, 0x0004, "MONOCHROME1")
+        .pixel_data("OB", pixels.clone());
+    let inverse_shape = SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 1, 8)
+        .cs(0x2050, 0x0020, "INVERSE")
+        .pixel_data("OB", pixels.clone());
+    let both = SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 1, 8)
+        .cs(0x0028, 0x0004, "MONOCHROME1")
+        .cs(0x2050, 0x0020, "INVERSE")
+        .pixel_data("OB", pixels.clone());
+
+    let cases = [
+        ("monochrome1", &monochrome1),
+        ("inverse_shape", &inverse_shape),
+        ("monochrome1_inverse", &both)
+    ];
+
+    for (name, dicom) in cases.iter() {
+        let png = run_dicom2png_with_args(name, dicom, &["--display"]).unwrap();
+        assert_eq!(png.bytes, inverted, "{}", name);
+    }
+
+    // Stored values are exported as they are
+    let png = run_dicom2png("monochrome1_stored", &monochrome1).unwrap();
+    assert_eq!(png.bytes, pixels);
+}
+
+#[test]
+fn ybr_full_422_is_upsampled_and_converted_to_rgb() {
+
+    // Y1 Y2 Cb Cr for each pair of pixels
+    let pixels: Vec<u8> = vec![
+        10, 200, 128, 128,
+        76, 76, 85, 255
+    ];
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 3, 8)
+        .cs(0x0028, 0x0004, "YBR_FULL_422")
+        .pixel_data("OB", pixels);
+
+    let png = run_dicom2png("ybr_full_422", &dicom).unwrap();
+
+    assert_eq!(png.color_type, png::ColorType::RGB);
+    assert_eq!(png.bytes, vec![
+        10, 10, 10, 200, 200, 200,
+        254, 0, 0, 254, 0, 0
+    ]);
+}
+
+#[test]
+fn planar_configuration_is_interleaved() {
+
+    let planes: Vec<u8> = vec![
+        1, 2, 3, 4,
+        10, 20, 30, 40,
+        100, 200, 250, 255
+    ];
+    let interleaved: Vec<u8> = vec![1, 10, 100, 2, 20, 200, 3, 30, 250, 4, 40, 255];
+
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 3, 8)
+        .us(0x0028, 0x0006, 1)
+        .pixel_data("OB", planes);
+
+    let png = run_dicom2png("planar_rgb8", &dicom).unwrap();
+    assert_eq!(png.bytes, interleaved);
+
+    // Each plane is made of whole big endian samples
+    let values: Vec<u16> = vec![0x0102, 0x0304, 0x1112, 0x1314, 0xA1A2, 0xA3A4];
+    let be_bytes: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect();
+
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_BE, 2, 1, 3, 16)
+        .us(0x0028, 0x0006, 1)
+        .pixel_data("OW", be_bytes);
+
+    let png = run_dicom2png("planar_rgb16_big_endian", &dicom).unwrap();
+    assert_eq!(png.bytes, vec![
+        0x01, 0x02, 0x11, 0x12, 0xA1, 0xA2,
+        0x03, 0x04, 0x13, 0x14, 0xA3, 0xA4
+    ]);
+}
+
+#[test]
+fn rle_segments_are_most_significant_byte_first() {
+
+    // R MSB, R LSB, G MSB, G LSB, B MSB, B LSB
+    let segments = vec![
+        vec![0x12, 0x56],
+        vec![0x34, 0x78],
+        vec![0xAB, 0x01],
+        vec![0xCD, 0x23],
+        vec![0xEF, 0x45],
+        vec![0x00, 0x67]
+    ];
+    let dicom = SyntheticDicom::image(RLE_LOSSLESS, 2, 1, 3, 16)
+        .pixel_sequence(Vec::new(), vec![rle_frame(&segments)]);
+
+    let png = run_dicom2png("rle_rgb16", &dicom).unwrap();
+
+    assert_eq!(png.bit_depth, png::BitDepth::Sixteen);
+    assert_eq!(png.bytes, vec![
+        0x12, 0x34, 0xAB, 0xCD, 0xEF, 0x00,
+        0x56, 0x78, 0x01, 0x23, 0x45, 0x67
+    ]);
+
+    // Short segments are padded, long segments are truncated
+    let segments = vec![
+        vec![0x01, 0x02],
+        vec![0x10, 0x20, 0x30, 0x40, 0x50]
+    ];
+    let dicom = SyntheticDicom::image(RLE_LOSSLESS, 2, 2, 1, 16)
+        .is(0x0028, 0x0008, 2)
+        .pixel_sequence(Vec::new(), vec![rle_frame(&segments), rle_frame(&segments)]);
+
+    let pngs = run_dicom2png_frames("rle_gray16_lengths", &dicom, 2).unwrap();
+
+    for png in pngs.iter() {
+        assert_eq!(png.bytes, vec![0x01, 0x10, 0x02, 0x20, 0x00, 0x30, 0x00, 0x40]);
+    }
+}
+
+#[test]
+fn jpeg_lossless_fixtures_decode_losslessly() {
+
+    // Selection value 1 only for the SV1 syntax
+    for sv in 1..=7 {
+        let (syntax, name) = match sv {
+            1 => ("1.2.840.10008.1.2.4.70", "jpeg_lossless_16bit_sv1.jpg".to_string()),
+            _ => ("1.2.840.10008.1.2.4.57", format!("jpeg_lossless_16bit_sv{}.jpg", sv))
+        };
+        assert_eq!(decode_fixture(syntax, &name, 8, 8, 1, 16), fixture_samples(8, 8, 1, 16), "{}", name);
+    }
+
+    for bits in [8, 12] {
+        let name = format!("jpeg_lossless_{}bit.jpg", bits);
+        let samples = decode_fixture("1.2.840.10008.1.2.4.70", &name, 8, 8, 1, bits);
+        assert_eq!(samples, fixture_samples(8, 8, 1, bits as u32), "{}", name);
+    }
+
+    // Restart markers every 2 rows
+    let samples = decode_fixture("1.2.840.10008.1.2.4.70", "jpeg_lossless_rgb_restart.jpg", 8, 8, 3, 8);
+    assert_eq!(samples, fixture_samples(8, 8, 3, 8));
+}
+
+#[test]
+fn jpeg_extended_12bit_decodes_to_known_samples() {
+
+    // Blocks with a DC of 16 then -16, quantized by 1
+    let dicom = SyntheticDicom::image("1.2.840.10008.1.2.4.51", 16, 8, 1, 16)
+        .bits_stored(12, 11)
+        .pixel_sequence(Vec::new(), vec![dc_only_jpeg(12, 16, 8, 1, &[16, -32])]);
+
+    let png = run_dicom2png("jpeg_extended_dc_only", &dicom).unwrap();
+
+    let row = [[2050; 8], [2046; 8]].concat();
+    assert_eq!(png_samples(&png), row.repeat(8));
+
+    // Encoded by libjpeg-turbo at quality 75, IDCTs may round differently from its
+    // own decoding (little endian samples)
+    let samples = decode_fixture("1.2.840.10008.1.2.4.51", "jpeg_extended_12bit.jpg", 16, 16, 1, 12);
+    let expected: Vec<u16> = fixture("jpeg_extended_12bit_libjpeg.raw")
+        .chunks(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    assert_eq!(samples.len(), expected.len());
+    for (i, (&v, &e)) in samples.iter().zip(expected.iter()).enumerate() {
+        assert!(v.abs_diff(e) <= 1, "sample {}: {} instead of {}", i, v, e);
+    }
+}
+
+#[test]
+fn jpeg_ls_fixtures_decode_to_known_samples() {
+
+    for bits in [8, 12, 16] {
+        let name = format!("jpeg_ls_{}bit.jls", bits);
+        let samples = decode_fixture("1.2.840.10008.1.2.4.80", &name, 8, 8, 1, bits);
+        assert_eq!(samples, fixture_samples(8, 8, 1, bits as u32), "{}", name);
+    }
+
+    // Component, line and sample interleaving
+    for ilv in 0..3 {
+        let name = format!("jpeg_ls_rgb_ilv{}.jls", ilv);
+        assert_eq!(decode_fixture("1.2.840.10008.1.2.4.80", &name, 8, 8, 3, 8), fixture_samples(8, 8, 3, 8), "{}", name);
+    }
+
+    // LSE segment with T1 = 2, T2 = 5, T3 = 9 and RESET = 16
+    let samples = decode_fixture("1.2.840.10008.1.2.4.80", "jpeg_ls_12bit_preset.jls", 8, 8, 1, 12);
+    assert_eq!(samples, fixture_samples(8, 8, 1, 12));
+
+    // Near-lossless samples are within NEAR of the source
+    let samples = decode_fixture("1.2.840.10008.1.2.4.81", "jpeg_ls_8bit_near2.jls", 8, 8, 1, 8);
+    let expected = fixture_samples(8, 8, 1, 8);
+    assert_eq!(samples.len(), expected.len());
+    assert!(samples != expected);
+    for (i, (&v, &e)) in samples.iter().zip(expected.iter()).enumerate() {
+        assert!(v.abs_diff(e) <= 2, "sample {}: {} instead of {}", i, v, e);
+    }
+}
+
+#[test]
+fn jpeg_2000_fixtures_decode_to_known_samples() {
+
+    // Reversible wavelet, J2K codestream
+    let samples = decode_fixture("1.2.840.10008.1.2.4.90", "jpeg_2000_16bit.j2k", 8, 8, 1, 16);
+    assert_eq!(samples, fixture_samples(8, 8, 1, 16));
+
+    // JP2 file with the reversible color transform
+    let samples = decode_fixture("1.2.840.10008.1.2.4.90", "jpeg_2000_rgb.jp2", 8, 8, 3, 8);
+    assert_eq!(samples, fixture_samples(8, 8, 3, 8));
+
+    // Irreversible wavelet without rate control, samples are off by the rounding of the transform
+    let samples = decode_fixture("1.2.840.10008.1.2.4.91", "jpeg_2000_16bit_irreversible.j2k", 8, 8, 1, 16);
+    let expected = fixture_samples(8, 8, 1, 16);
+    assert_eq!(samples.len(), expected.len());
+    for (i, (&v, &e)) in samples.iter().zip(expected.iter()).enumerate() {
+        assert!(v.abs_diff(e) <= 16, "sample {}: {} instead of {}", i, v, e);
+    }
+}
+
+#[test]
+fn htj2k_fixture_decodes_losslessly() {
+
+    // One HT code-block, RPCL progression
+    let samples = decode_fixture("1.2.840.10008.1.2.4.202", "htj2k_16bit.j2k", 16, 2, 1, 16);
+    assert_eq!(samples, fixture_samples(16, 2, 1, 16));
+}
+
+fn palette_image(name: &str, indices: Vec<u8>, palettes: &[(&[u16], u16, Vec<u16>)]) -> Vec<[u16; 3]> {
+
+    let mut dicom = SyntheticDicom::image(EXPLICIT_VR_LE, indices.len() as u16, 1, 1, 8)
+        .cs(0x0028, 0x0004, "PALETTE COLOR");
+
+    for (channel, (descriptor, data_elem, data)) in palettes.iter().enumerate() {
+        dicom = dicom
+            .words(0x0028, 0x1101 + channel as u16, "US", descriptor)
+            .words(0x0028, *data_elem + channel as u16, "OW", data);
+    }
+
+    let png = run_dicom2png(name, &dicom.pixel_data("OB", indices)).unwrap();
+    assert_eq!(png.color_type, png::ColorType::RGB);
+    assert_eq!(png.bit_depth, png::BitDepth::Sixteen);
+
+    png.bytes
+        .chunks_exact(6)
+        .map(|p| [
+            u16::from_be_bytes([p[0], p[1]]),
+            u16::from_be_bytes([p[2], p[3]]),
+            u16::from_be_bytes([p[4], p[5]])
+        ])
+        .collect()
+}
+
+#[test]
+fn palette_color_follows_the_descriptor() {
+
+    // 4 entries starting at index 10, indices outside are clamped
+    let descriptor: &[u16] = &[4, 10, 16];
+    let pixels = palette_image("palette_descriptor", vec![8, 10, 11, 12, 13, 200], &[
+        (descriptor, 0x1201, vec![0, 1000, 30000, 65535]),
+        (descriptor, 0x1201, vec![10, 20, 30, 40]),
+        (descriptor, 0x1201, vec![65535, 40000, 20000, 0])
+    ]);
+
+    assert_eq!(pixels, vec![
+        [0, 10, 65535],
+        [0, 10, 65535],
+        [1000, 20, 40000],
+        [30000, 30, 20000],
+        [65535, 40, 0],
+        [65535, 40, 0]
+    ]);
+
+    // 8-bit entries are scaled to 16 bits, one per word or packed two per word
+    let descriptor: &[u16] = &[4, 0, 8];
+    let pixels = palette_image("palette_8bit", vec![0, 1, 2, 3], &[
+        (descriptor, 0x1201, vec![0, 1, 128, 255]),
+        (descriptor, 0x1201, vec![0x0100, 0xFF80]),
+        (descriptor, 0x1201, vec![255, 255, 255, 255])
+    ]);
+
+    assert_eq!(pixels, vec![
+        [0, 0, 65535],
+        [257, 257, 65535],
+        [32896, 32896, 65535],
+        [65535, 65535, 65535]
+    ]);
+}
+
+#[test]
+fn segmented_palette_color_is_expanded() {
+
+    // Discrete [0, 100], linear to 300, indirect copy of the first segment, linear to 500
+    let segments = vec![
+        0, 2, 0, 100,
+        1, 2, 300,
+        2, 1, 0, 0,
+        1, 2, 500
+    ];
+    let descriptor: &[u16] = &[8, 0, 16];
+    let pixels = palette_image("segmented_palette", (0..8).collect(), &[
+        (descriptor, 0x1221, segments.clone()),
+        (descriptor, 0x1201, vec![0; 8]),
+        (descriptor, 0x1221, segments)
+    ]);
+
+    let expected: Vec<u16> = vec![0, 100, 200, 300, 0, 100, 300, 500];
+    assert_eq!(pixels.iter().map(|p| p[0]).collect::<Vec<_>>(), expected);
+    assert_eq!(pixels.iter().map(|p| p[2]).collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn pixel_data_with_other_long_vr() {
+
+    let values: Vec<u16> = vec![0x0102, 0x0304, 0xA1A2, 0xFFFE];
+    let le_bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 1, 16)
+        .pixel_data("OL", le_bytes);
+
+    let png = run_dicom2png("other_long_gray16", &dicom).unwrap();
+
+    assert_eq!(png.bytes, vec![0x01, 0x02, 0x03, 0x04, 0xA1, 0xA2, 0xFF, 0xFE]);
+}
+
+#[test]
+fn float_pixel_data_is_displayed_over_its_range() {
+
+    let values: Vec<f32> = vec![0.0, 255.0, 51.0, 102.0];
+    let float = SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 1, 32)
+        .element(0x7FE0, 0x0008, "OF", values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect());
+
+    let values: Vec<f64> = vec![-1000.0, 1550.0, -490.0, 20.0];
+    let double = SyntheticDicom::image(EXPLICIT_VR_BE, 2, 2, 1, 64)
+        .element(0x7FE0, 0x0009, "OD", values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect());
+
+    // PNG has no float samples, the stored values are exported over their range too
+    for (name, dicom) in [("float", &float), ("double_big_endian", &double)].iter() {
+        for args in [&["--display"][..], &[]] {
+            let png = run_dicom2png_with_args(name, dicom, args).unwrap();
+            assert_eq!(png.bit_depth, png::BitDepth::Eight);
+            assert_eq!(png.bytes, vec![0, 255, 51, 102], "{} {:?}", name, args);
+        }
+    }
+}
+
+/// Readable overlays of a file, errors of the other overlay groups, and the
+/// display frames with the readable overlays drawn
+fn overlays_in_process(name: &str, dicom: &SyntheticDicom) -> (Vec<oxydicom::Overlay>, Vec<oxydicom::DecodeError>, Vec<oxydicom::RawImage>) {
+
+    let path = std::env::temp_dir().join("oxydicom-tests").join(format!("{}.dcm", name));
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, dicom.to_bytes()).unwrap();
+
+    let dicom = oxydicom::open_dicom(&path).unwrap();
+    let (mut overlays, mut errors) = (Vec::new(), Vec::new());
+    for overlay in oxydicom::get_overlays(&dicom) {
+        match overlay {
+            Ok(overlay) => overlays.push(overlay),
+            Err(e) => errors.push(e)
+        }
+    }
+
+    let mut frames = oxydicom::get_display_frames(&dicom).unwrap();
+    for (i, frame) in frames.iter_mut().enumerate() {
+        oxydicom::draw_overlays(frame, &overlays, i).unwrap();
+    }
+
+    (overlays, errors, frames)
+}
+
+/// 8-bit image displayed with its stored values
+fn identity_window(dicom: SyntheticDicom) -> SyntheticDicom {
+    dicom.ds(0x0028, 0x1050, "128").ds(0x0028, 0x1051, "256")
+}
+
+fn overlay_plane(dicom: SyntheticDicom, group: u16, rows: u16, columns: u16, origin: [u16; 2]) -> SyntheticDicom {
+    dicom.us(group, 0x0010, rows)
+        .us(group, 0x0011, columns)
+        .cs(group, 0x0040, "G")
+        .words(group, 0x0050, "SS", &origin)
+}
+
+#[test]
+fn packed_overlay_data_is_drawn_at_the_overlay_origin() {
+
+    // 3x2 plane at row 2, column 2: bits 0, 2, 3 and 5 set
+    let dicom = overlay_plane(identity_window(SyntheticDicom::image(EXPLICIT_VR_LE, 4, 3, 1, 8)), 0x6000, 2, 3, [2, 2])
+        .us(0x6000, 0x0100, 1)
+        .us(0x6000, 0x0102, 0)
+        .element(0x6000, 0x3000, "OW", vec![0x2D, 0x00])
+        .pixel_data("OB", (0..12).map(|i| i * 10).collect());
+
+    let (overlays, errors, frames) = overlays_in_process("overlay_packed", &dicom);
+
+    assert!(errors.is_empty());
+    let overlay = &overlays[0];
+    assert_eq!((overlay.rows, overlay.columns, overlay.origin), (2, 3, (1, 1)));
+    assert_eq!(overlay.bitmaps, vec![vec![true, false, true, true, false, true]]);
+
+    let mut expected: Vec<u8> = (0..12).map(|i| i * 10).collect();
+    for pixel in [5, 7, 9, 11] {
+        expected[pixel] = 255;
+    }
+    assert_eq!(frames[0].bytes, expected);
+}
+
+#[test]
+fn multi_frame_overlays_start_at_their_image_frame() {
+
+    // Overlay frames of 6 bits, packed without gaps from the second image frame
+    let dicom = SyntheticDicom::image(EXPLICIT_VR_LE, 3, 2, 1, 8).is(0x0028, 0x0008, 3);
+    let dicom = overlay_plane(identity_window(dicom), 0x6000, 2, 3, [1, 1])
+        .is(0x6000, 0x0015, 2)
+        .us(0x6000, 0x0051, 2)
+        .element(0x6000, 0x3000, "OW", vec![0xA1, 0x07])
+        .pixel_data("OB", vec![0; 18]);
+
+    let (overlays, errors, frames) = overlays_in_process("overlay_multi_frame", &dicom);
+
+    assert!(errors.is_empty());
+    assert_eq!(overlays[0].first_frame, 1);
+    assert_eq!(frames[0].bytes, vec![0; 6]);
+    assert_eq!(frames[1].bytes, vec![255, 0, 0, 0, 0, 255]);
+    assert_eq!(frames[2].bytes, vec![0, 255, 255, 255, 255, 0]);
+}
+
+#[test]
+fn overlays_embedded_in_the_high_bits_are_masked_from_the_image() {
+
+    // Bit 15 of 12-bit samples, the overlay Bits Allocated is that of the image
+    let dicom = overlay_plane(SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 1, 16), 0x6000, 2, 2, [1, 1])
+        .bits_stored(12, 11)
+        .us(0x6000, 0x0102, 15)
+        .words(0x7FE0, 0x0010, "OW", &[0x8001, 0x0002, 0x8003, 0x0004]);
+
+    let (overlays, errors, frames) = overlays_in_process("overlay_embedded", &dicom);
+
+    assert!(errors.is_empty());
+    assert_eq!(overlays[0].bitmaps, vec![vec![true, false, true, false]]);
+    assert_eq!(frames[0].bytes, vec![255, 85, 255, 255]);
+}
+
+#[test]
+fn malformed_overlay_groups_do_not_prevent_display() {
+
+    // 6002 is embedded in encapsulated pixel data, 6004 has a single origin value
+    let dicom = identity_window(SyntheticDicom::image(RLE_LOSSLESS, 2, 2, 1, 8));
+    let dicom = overlay_plane(dicom, 0x6000, 2, 2, [1, 1]).element(0x6000, 0x3000, "OW", vec![0x09, 0x00]);
+    let dicom = overlay_plane(dicom, 0x6002, 2, 2, [1, 1]).us(0x6002, 0x0102, 7);
+    let dicom = overlay_plane(dicom, 0x6004, 2, 2, [1, 1])
+        .words(0x6004, 0x0050, "SS", &[1])
+        .element(0x6004, 0x3000, "OW", vec![0x0F, 0x00])
+        .pixel_sequence(Vec::new(), vec![rle_frame(&[vec![0, 0, 0, 0]])]);
+
+    let (overlays, errors, frames) = overlays_in_process("overlay_malformed", &dicom);
+
+    assert_eq!(overlays.len(), 1);
+    assert_eq!(overlays[0].group, 0x6000);
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].to_string(), "Overlay 6002: Pixel data is not native");
+    assert!(matches!(errors[1], oxydicom::DecodeError::InvalidAttribute { .. }));
+    assert_eq!(frames[0].bytes, vec![255, 0, 0, 255]);
+}
+
+/// Malformed files make dicom2png fail with an error message, never with a panic
+fn assert_decoding_error(name: &str, bytes: &[u8], args: &[&str], message: &str) {
+    match run_dicom2png_on_bytes(name, bytes, args) {
+        Ok(_) => panic!("{}: decoded without error", name),
+        Err(stderr) => {
+            assert!(!stderr.contains("panicked"), "{}: {}", name, stderr);
+            assert!(stderr.contains(message), "{}: expected {:?} in {}", name, message, stderr);
+        }
+    }
+}
+
+#[test]
+fn malformed_attributes_and_pixel_data_are_reported_as_errors() {
+
+    let image = SyntheticDicom::image(EXPLICIT_VR_LE, 4, 4, 1, 8).pixel_data("OB", vec![0; 16]);
+
+    let cases = vec![
+        ("missing_rows", image.clone().without(0x0028, 0x0010), "Missing attribute (0028,0010)"),
+        ("zero_columns", image.clone().us(0x0028, 0x0011, 0), "Empty image"),
+        ("bad_samples_per_pixel", image.clone().us(0x0028, 0x0002, 7), "Invalid attribute (0028,0002)"),
+        ("bad_high_bit", image.clone().bits_stored(8, 9), "Invalid attribute (0028,0101)"),
+        ("missing_pixel_data", image.clone().without(0x7FE0, 0x0010), "Missing attribute (7FE0,0010)"),
+        ("truncated_pixel_data", image.clone().pixel_data("OB", vec![0; 14]), "Pixel data too short"),
+        ("too_many_frames", image.clone().is(0x0028, 0x0008, 2), "Pixel data too short"),
+        (
+            "unsupported_transfer_syntax",
+            SyntheticDicom::image("1.2.840.10008.1.2.4.100", 4, 4, 1, 8).pixel_sequence(Vec::new(), vec![vec![0; 16]]),
+            "Unhandled transfer syntax: 1.2.840.10008.1.2.4.100"
+        ),
+        (
+            "palette_too_short",
+            image.clone()
+                .cs(0x0028, 0x0004, "PALETTE COLOR")
+                .words(0x0028, 0x1101, "US", &[4, 0, 16])
+                .words(0x0028, 0x1102, "US", &[4, 0, 16])
+                .words(0x0028, 0x1103, "US", &[4, 0, 16])
+                .words(0x0028, 0x1201, "OW", &[0, 1])
+                .words(0x0028, 0x1202, "OW", &[0, 1])
+                .words(0x0028, 0x1203, "OW", &[0, 1]),
+            "Invalid attribute (0028,1201)"
+        ),
+        (
+            "bad_window_width",
+            image.clone().ds(0x0028, 0x1050, "10").ds(0x0028, 0x1051, "0"),
+            "Invalid attribute (0028,1051)"
+        ),
+        ("infinite_rescale_slope", image.clone().ds(0x0028, 0x1053, "inf"), "Invalid attribute (0028,1053)"),
+        ("nan_rescale_intercept", image.clone().ds(0x0028, 0x1052, "NaN"), "Invalid attribute (0028,1052)"),
+        (
+            "infinite_window_center",
+            image.clone().ds(0x0028, 0x1050, "-Infinity").ds(0x0028, 0x1051, "10"),
+            "Invalid attribute (0028,1050)"
+        ),
+        (
+            "overflowing_window_width",
+            image.clone().ds(0x0028, 0x1050, "10").ds(0x0028, 0x1051, "1e400"),
+            "Invalid attribute (0028,1051)"
+        )
+    ];
+
+    for (name, dicom, message) in cases.iter() {
+        assert_decoding_error(name, &dicom.to_bytes(), &["--display"], message);
+    }
+
+    // Valid but extreme: modality values of ±1e30 looked up in a VOI LUT starting at -2
+    let extreme_rescale = SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 1, 8)
+        .us(0x0028, 0x0103, 1)
+        .ds(0x0028, 0x1052, "0")
+        .ds(0x0028, 0x1053, "1e30")
+        .element(0x0028, 0x3010, "SQ", lut_sequence(0xFFFE, 8, &[0, 100, 200, 255]))
+        .pixel_data("OB", vec![0, 1, 0xFF, 0]);
+
+    let png = run_dicom2png_with_args("extreme_rescale", &extreme_rescale, &["--display"]).unwrap();
+    assert_eq!(png.bytes, vec![200, 255, 0, 200]);
+
+    // Finite slope, modality values past the f32 range
+    let overflowing_rescale = image.clone().ds(0x0028, 0x1053, "1e300").pixel_data("OB", (0..16).collect());
+    assert!(run_dicom2png_with_args("overflowing_rescale", &overflowing_rescale, &["--display"]).is_ok());
+}
+
+#[test]
+fn corrupt_codestreams_are_reported_as_errors() {
+
+    let segments = vec![vec![1, 2, 3, 4]];
+    let rle = |fragment: Vec<u8>| SyntheticDicom::image(RLE_LOSSLESS, 2, 2, 1, 8)
+        .pixel_sequence(Vec::new(), vec![fragment]);
+
+    // The RLE header has room for 15 offsets
+    let mut too_many_segments = rle_frame(&segments);
+    too_many_segments[0] = 16;
+    let mut bad_offset = rle_frame(&segments);
+    bad_offset[4] = 0xF0;
+
+    let jpeg_extended = |w: u16, codestream: Vec<u8>| SyntheticDicom::image("1.2.840.10008.1.2.4.51", w, 8, 1, 16)
+        .pixel_sequence(Vec::new(), vec![codestream]);
+
+    // MAXVAL, T1, T2, T3 and RESET of a 12-bit JPEG-LS LSE segment
+    let jpeg_ls_preset = |preset: [u16; 5]| {
+        let mut codestream = fixture("jpeg_ls_12bit_preset.jls");
+        let lse = codestream.windows(2).position(|m| m == [0xFF, 0xF8]).unwrap();
+        for (i, v) in preset.iter().enumerate() {
+            codestream[lse + 5 + 2 * i..lse + 7 + 2 * i].copy_from_slice(&v.to_be_bytes());
+        }
+        SyntheticDicom::image("1.2.840.10008.1.2.4.80", 8, 8, 1, 16)
+            .bits_stored(12, 11)
+            .pixel_sequence(Vec::new(), vec![codestream])
+    };
+
+    let cases = vec![
+        ("rle_short_header", rle(vec![1, 0, 0, 0]), "RLE: not enough bytes for header"),
+        ("rle_too_many_segments", rle(too_many_segments), "RLE: header has 16 segments"),
+        ("rle_bad_offset", rle(bad_offset), "RLE: bad offset for segment 1"),
+        ("rle_missing_segments", rle(rle_frame(&[vec![1], vec![2]])), "RLE: 2 segments, expected 1"),
+        (
+            "rle_larger_than_segments",
+            SyntheticDicom::image(RLE_LOSSLESS, 65535, 65535, 1, 8).pixel_sequence(Vec::new(), vec![rle_frame(&segments)]),
+            "RLE: segment 1: 6 bytes cannot hold 4294836225 samples"
+        ),
+        (
+            "jpeg_garbage",
+            SyntheticDicom::image("1.2.840.10008.1.2.4.50", 2, 2, 1, 8).pixel_sequence(Vec::new(), vec![vec![0x12; 16]]),
+            "JPEG"
+        ),
+        (
+            "jpeg_lossless_truncated_frame_header",
+            SyntheticDicom::image("1.2.840.10008.1.2.4.70", 2, 2, 1, 8)
+                .pixel_sequence(Vec::new(), vec![vec![0xFF, 0xD8, 0xFF, 0xC3, 0x00, 0x08, 0x08, 0x00]]),
+            "JPEG: truncated marker segment"
+        ),
+        (
+            "jpeg_lossless_frame_larger_than_image",
+            SyntheticDicom::image("1.2.840.10008.1.2.4.70", 2, 2, 1, 8).pixel_sequence(Vec::new(), vec![vec![
+                0xFF, 0xD8, 0xFF, 0xC3, 0x00, 0x0B, 0x08, 0xFF, 0xFF, 0xFF, 0xFF, 0x01, 0x01, 0x11, 0x00, 0xFF, 0xD9
+            ]]),
+            "JPEG: frame is 65535x65535, expected 2x2"
+        ),
+        (
+            // DC differences have at most 15 bits in a 12-bit frame
+            "jpeg_extended_dc_difference_too_large",
+            jpeg_extended(8, dc_only_jpeg(12, 8, 8, 65535, &[65535])),
+            "JPEG: invalid DC difference size: 16"
+        ),
+        (
+            "jpeg_extended_dc_overflow",
+            jpeg_extended(16, dc_only_jpeg(12, 16, 8, 65535, &[32767, 32767])),
+            "JPEG: DC coefficient overflow"
+        ),
+        (
+            "jpeg_lossless_larger_than_codestream",
+            SyntheticDicom::image("1.2.840.10008.1.2.4.70", 65535, 65535, 1, 8).pixel_sequence(Vec::new(), vec![vec![
+                0xFF, 0xD8, 0xFF, 0xC3, 0x00, 0x0B, 0x08, 0xFF, 0xFF, 0xFF, 0xFF, 0x01, 0x01, 0x11, 0x00, 0xFF, 0xD9
+            ]]),
+            "JPEG Lossless: 17 bytes cannot hold 4294836225 samples"
+        ),
+        (
+            "jpeg_ls_without_frame",
+            SyntheticDicom::image("1.2.840.10008.1.2.4.80", 2, 2, 1, 8)
+                .pixel_sequence(Vec::new(), vec![vec![0xFF, 0xD8, 0xFF, 0xD9]]),
+            "JPEG-LS: missing frame header"
+        ),
+        ("jpeg_ls_maxval_too_large", jpeg_ls_preset([4096, 2, 5, 9, 16]), "JPEG-LS: invalid MAXVAL 4096"),
+        ("jpeg_ls_unordered_thresholds", jpeg_ls_preset([4095, 9, 5, 9, 16]), "JPEG-LS: invalid thresholds 9, 5, 9"),
+        ("jpeg_ls_threshold_above_maxval", jpeg_ls_preset([255, 2, 5, 300, 16]), "JPEG-LS: invalid thresholds 2, 5, 300"),
+        ("jpeg_ls_reset_too_small", jpeg_ls_preset([4095, 2, 5, 9, 2]), "JPEG-LS: invalid RESET value 2"),
+        ("jpeg_ls_reset_too_large", jpeg_ls_preset([4095, 2, 5, 9, 65535]), "JPEG-LS: invalid RESET value 65535"),
+        (
+            "jpeg_2000_garbage",
+            SyntheticDicom::image("1.2.840.10008.1.2.4.90", 2, 2, 1, 8).pixel_sequence(Vec::new(), vec![vec![0x12; 16]]),
+            "JPEG2000: data is neither a J2K codestream nor a JP2 file"
+        )
+    ];
+
+    for (name, dicom, message) in cases.iter() {
+        assert_decoding_error(name, &dicom.to_bytes(), &[], message);
+    }
+}
+
+#[test]
+fn truncated_files_are_reported_as_errors() {
+
+    let segments = vec![vec![0x12, 0x56, 0x9A, 0xDE], vec![0x34, 0x78, 0xBC, 0xF0]];
+    let files = [
+        ("truncated_native", SyntheticDicom::image(EXPLICIT_VR_LE, 2, 2, 1, 16).pixel_data("OW", vec![0x55; 8])),
+        ("truncated_rle", SyntheticDicom::image(RLE_LOSSLESS, 2, 2, 1, 16).pixel_sequence(Vec::new(), vec![rle_frame(&segments)]))
+    ];
+
+    for (name, dicom) in files.iter() {
+
+        let bytes = dicom.to_bytes();
+        assert!(run_dicom2png_on_bytes(name, &bytes, &[]).is_ok(), "{}", name);
+
+        // Every cut of the pixel data, then every 16 bytes of the data set
+        let cuts = (bytes.len() - 80..bytes.len()).chain((132..bytes.len() - 80).step_by(16));
+
+        for len in cuts {
+            // Truncated RLE segments are padded, some cuts still decode
+            if let Err(stderr) = run_dicom2png_on_bytes(name, &bytes[..len], &[]) {
+                assert!(!stderr.contains("panicked"), "{} cut at {}: {}", name, len, stderr);
+            }
+        }
+    }
+}
+
+/// Decodes the display frames in the test process, `Err` if the decoding panicked
+fn decode_in_process(name: &str, bytes: &[u8]) -> Result<Result<Vec<oxydicom::RawImage>, oxydicom::DecodeError>, String> {
+
+    let path = std::env::temp_dir().join("oxydicom-tests").join(format!("{}.dcm", name));
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, bytes).unwrap();
+
+    std::panic::catch_unwind(|| oxydicom::open_dicom(&path).and_then(|dicom| oxydicom::get_display_frames(&dicom)))
+        .map_err(|e| e.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| e.downcast_ref::<String>().cloned())
+            .unwrap_or_default())
+}
+
+#[test]
+fn truncated_and_corrupt_codestreams_do_not_panic() {
+
+    let jpeg = dc_only_jpeg(8, 16, 8, 1, &[8, -16]);
+    let files = [
+        ("fuzzed_jpeg_baseline", SyntheticDicom::image("1.2.840.10008.1.2.4.50", 16, 8, 1, 8).pixel_sequence(Vec::new(), vec![jpeg])),
+        ("fuzzed_jpeg_extended", fixture_dicom("1.2.840.10008.1.2.4.51", "jpeg_extended_12bit.jpg", 16, 16, 1, 12)),
+        ("fuzzed_jpeg_lossless", fixture_dicom("1.2.840.10008.1.2.4.57", "jpeg_lossless_16bit_sv7.jpg", 8, 8, 1, 16)),
+        ("fuzzed_jpeg_lossless_rgb", fixture_dicom("1.2.840.10008.1.2.4.70", "jpeg_lossless_rgb_restart.jpg", 8, 8, 3, 8)),
+        ("fuzzed_jpeg_ls_preset", fixture_dicom("1.2.840.10008.1.2.4.80", "jpeg_ls_12bit_preset.jls", 8, 8, 1, 12)),
+        ("fuzzed_jpeg_ls_near", fixture_dicom("1.2.840.10008.1.2.4.81", "jpeg_ls_8bit_near2.jls", 8, 8, 1, 8)),
+        ("fuzzed_jpeg_ls_rgb", fixture_dicom("1.2.840.10008.1.2.4.80", "jpeg_ls_rgb_ilv2.jls", 8, 8, 3, 8)),
+        ("fuzzed_jpeg_2000", fixture_dicom("1.2.840.10008.1.2.4.90", "jpeg_2000_16bit.j2k", 8, 8, 1, 16)),
+        ("fuzzed_jpeg_2000_rgb", fixture_dicom("1.2.840.10008.1.2.4.90", "jpeg_2000_rgb.jp2", 8, 8, 3, 8)),
+        ("fuzzed_htj2k", fixture_dicom("1.2.840.10008.1.2.4.202", "htj2k_16bit.j2k", 16, 2, 1, 16))
+    ];
+
+    for (name, dicom) in files.iter() {
+
+        assert!(matches!(decode_in_process(name, &dicom.to_bytes()), Ok(Ok(_))), "{}", name);
+
+        let (_, fragments) = dicom.pixel_sequence.as_ref().unwrap();
+        let codestream = &fragments[0];
+        let with_codestream = |mut fragment: Vec<u8>| {
+            if fragment.len() % 2 == 1 {
+                fragment.push(0);
+            }
+            dicom.clone().pixel_sequence(Vec::new(), vec![fragment]).to_bytes()
+        };
+
+        // Every cut, then every byte set to 0x00, 0xFF or flipped bits
+        for len in 0..codestream.len() {
+            if let Err(message) = decode_in_process(name, &with_codestream(codestream[..len].to_vec())) {
+                panic!("{} cut at {}: {}", name, len, message);
+            }
+        }
+
+        for i in 0..codestream.len() {
+            let value = [0x00, 0xFF, codestream[i] ^ 0x5A][i % 3];
+            let mut mutated = codestream.clone();
+            mutated[i] = value;
+            if let Err(message) = decode_in_process(name, &with_codestream(mutated)) {
+                panic!("{} byte {} set to {:#04x}: {}", name, i, value, message);
+            }
+        }
+    }
+}
+
+/// Proprietary codec storing the samples inverted, registered from outside the crate
+struct InvertedCodec;
+
+impl oxydicom::PixelCodec for InvertedCodec {
+    fn name(&self) -> &str { "Inverted" }
+    fn decode(&self, bytes: &[u8], format: &oxydicom::Format) -> Result<Vec<u8>, oxydicom::DecodeError> {
+        let len = (format.w * format.h * format.channels * format.channel_depth) as usize;
+        match bytes.get(..len) {
+            Some(samples) => Ok(samples.iter().map(|v| 255 - v).collect()),
+            None => Err(oxydicom::DecodeError::corrupt("Inverted: frame too short"))
+        }
+    }
+}
+
+#[test]
+fn registered_codecs_decode_their_transfer_syntax() {
+
+    const INVERTED: &str = "1.2.826.0.1.3680043.9.7433.1";
+
+    let pixels: Vec<u8> = (0..16u8).map(|i| i * 16).collect();
+    let inverted = pixels.iter().map(|v| 255 - v).collect();
+    let bytes = SyntheticDicom::image(INVERTED, 4, 4, 1, 8)
+        .pixel_sequence(Vec::new(), vec![inverted])
+        .to_bytes();
+
+    let path = std::env::temp_dir().join("oxydicom-tests").join("registered_codec.dcm");
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, &bytes).unwrap();
+
+    assert!(matches!(
+        oxydicom::open_dicom(&path).and_then(|dicom| oxydicom::get_frames(&dicom)),
+        Err(oxydicom::DecodeError::UnsupportedTransferSyntax(_))
+    ));
+
+    // Registered with the padding of the file
+    oxydicom::register_codec(&format!("{}\0", INVERTED), std::sync::Arc::new(InvertedCodec));
+
+    let dicom = oxydicom::open_dicom(&path).unwrap();
+    let frames = oxydicom::get_frames(&dicom).unwrap();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].bytes, pixels);
+    assert_eq!(oxydicom::get_codec(INVERTED).unwrap().name(), "Inverted");
+}